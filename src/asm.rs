@@ -0,0 +1,151 @@
+use std::{
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+/// A single line of Hack assembly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Instruction {
+    /// `@value`, where value is a constant or a symbol.
+    A(String),
+    /// `dest=comp;jump`
+    C {
+        dest: Option<String>,
+        comp: String,
+        jump: Option<String>,
+    },
+    /// `(symbol)`, binds a symbol to the address of the next instruction.
+    Label(String),
+    Comment(String),
+}
+
+impl Instruction {
+    pub fn c(dest: Option<&str>, comp: &str, jump: Option<&str>) -> Instruction {
+        Instruction::C {
+            dest: dest.map(String::from),
+            comp: String::from(comp),
+            jump: jump.map(String::from),
+        }
+    }
+
+    /// Whether the instruction occupies a ROM word once assembled.
+    pub fn is_code(&self) -> bool {
+        matches!(self, Instruction::A(_) | Instruction::C { .. })
+    }
+
+    /// Whether a C-instruction stores its result in `register` (`'A'`, `'D'` or `'M'`).
+    pub fn writes(&self, register: char) -> bool {
+        match self {
+            Instruction::C {
+                dest: Some(dest), ..
+            } => dest.contains(register),
+            _ => false,
+        }
+    }
+
+    /// Whether a C-instruction reads `register` (`'A'`, `'D'` or `'M'`) in its computation.
+    pub fn reads(&self, register: char) -> bool {
+        match self {
+            Instruction::C { comp, .. } => comp.contains(register),
+            _ => false,
+        }
+    }
+
+    pub fn jumps(&self) -> bool {
+        matches!(self, Instruction::C { jump: Some(_), .. })
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::A(value) => write!(f, "@{value}"),
+            Instruction::C { dest, comp, jump } => {
+                if let Some(dest) = dest {
+                    write!(f, "{dest}=")?;
+                }
+                write!(f, "{comp}")?;
+                if let Some(jump) = jump {
+                    write!(f, ";{jump}")?;
+                }
+                Ok(())
+            }
+            Instruction::Label(label) => write!(f, "({label})"),
+            Instruction::Comment(comment) => write!(f, "// {comment}"),
+        }
+    }
+}
+
+/// Whether `name` is a valid Hack symbol: letters, digits, `_`, `.`, `$` and
+/// `:`, not starting with a digit.
+pub fn is_symbol(name: &str) -> bool {
+    let valid = |c: char| c.is_ascii_alphanumeric() || "_.$:".contains(c);
+    !name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit()) && name.chars().all(valid)
+}
+
+impl FromStr for Instruction {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let line = line.trim();
+        if let Some(comment) = line.strip_prefix("//") {
+            return Ok(Instruction::Comment(String::from(comment.trim())));
+        }
+        if let Some(value) = line.strip_prefix('@') {
+            if value.is_empty() || value.contains(char::is_whitespace) {
+                return Err(format!("Error: Invalid A-instruction: {line}"));
+            }
+            return Ok(Instruction::A(String::from(value)));
+        }
+        if let Some(label) = line.strip_prefix('(') {
+            return match label.strip_suffix(')') {
+                Some(label) if !label.is_empty() => Ok(Instruction::Label(String::from(label))),
+                _ => Err(format!("Error: Invalid label: {line}")),
+            };
+        }
+
+        let (dest, rest) = match line.split_once('=') {
+            Some((dest, rest)) => (Some(dest.trim()), rest),
+            None => (None, line),
+        };
+        let (comp, jump) = match rest.split_once(';') {
+            Some((comp, jump)) => (comp.trim(), Some(jump.trim())),
+            None => (rest.trim(), None),
+        };
+        if comp.is_empty() || dest == Some("") || jump == Some("") {
+            return Err(format!("Error: Invalid C-instruction: {line}"));
+        }
+        Ok(Instruction::c(dest, comp, jump))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_symbol, Instruction};
+
+    #[test]
+    fn parse_and_display_round_trip() {
+        for line in [
+            "@SP", "@17", "D=M", "AM=M-1", "0;JMP", "D;JNE", "(LOOP)", "// add",
+        ] {
+            let instruction: Instruction = line.parse().unwrap();
+            assert_eq!(instruction.to_string(), line);
+        }
+        assert_eq!(
+            "MD=D+1;JGT".parse::<Instruction>().unwrap(),
+            Instruction::c(Some("MD"), "D+1", Some("JGT"))
+        );
+        assert!("@".parse::<Instruction>().is_err());
+        assert!("=D".parse::<Instruction>().is_err());
+    }
+
+    #[test]
+    fn recognizes_symbols() {
+        for name in ["Main", "Sys.init", "Main.fib$ret.3", "_x:1"] {
+            assert!(is_symbol(name), "{name}");
+        }
+        for name in ["", "1st", "my-file", "My File", "Main.vm\u{e9}"] {
+            assert!(!is_symbol(name), "{name}");
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display},
+};
+
+use crate::asm::Instruction;
+
+/// First RAM address handed out to assembler variables.
+const FIRST_VARIABLE: u16 = 16;
+
+const PREDEFINED: [(&str, u16); 7] = [
+    ("SP", 0),
+    ("LCL", 1),
+    ("ARG", 2),
+    ("THIS", 3),
+    ("THAT", 4),
+    ("SCREEN", 16384),
+    ("KBD", 24576),
+];
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct AssembleError {
+    /// 1-based line of the offending instruction.
+    pub line: usize,
+    pub kind: AssembleErrorKind,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AssembleErrorKind {
+    Syntax(String),
+    UnknownComp(String),
+    UnknownDest(String),
+    UnknownJump(String),
+    ConstantTooLarge(String),
+    DuplicateLabel(String),
+}
+
+impl Display for AssembleErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssembleErrorKind::Syntax(line) => write!(f, "invalid instruction `{line}`"),
+            AssembleErrorKind::UnknownComp(comp) => write!(f, "unknown computation `{comp}`"),
+            AssembleErrorKind::UnknownDest(dest) => write!(f, "unknown destination `{dest}`"),
+            AssembleErrorKind::UnknownJump(jump) => write!(f, "unknown jump `{jump}`"),
+            AssembleErrorKind::ConstantTooLarge(value) => {
+                write!(f, "constant {value} does not fit in an A-instruction")
+            }
+            AssembleErrorKind::DuplicateLabel(label) => {
+                write!(f, "label `{label}` is defined more than once")
+            }
+        }
+    }
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl Error for AssembleError {}
+
+/// Parses the text of an `.asm` file, dropping comments and blank lines.
+/// Returns each instruction with its 1-based line number.
+pub fn parse(source: &str) -> Result<Vec<(usize, Instruction)>, AssembleError> {
+    let mut instructions = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let code = line.split("//").next().unwrap_or("").trim();
+        if code.is_empty() {
+            continue;
+        }
+        let instruction = code.parse().map_err(|_| AssembleError {
+            line: i + 1,
+            kind: AssembleErrorKind::Syntax(String::from(code)),
+        })?;
+        instructions.push((i + 1, instruction));
+    }
+    Ok(instructions)
+}
+
+/// The predefined symbols: `SP`..`THAT`, `R0`-`R15`, `SCREEN` and `KBD`.
+pub fn predefined_symbols() -> HashMap<String, u16> {
+    let registers = (0..16).map(|r| (format!("R{r}"), r));
+    PREDEFINED
+        .iter()
+        .map(|&(name, address)| (String::from(name), address))
+        .chain(registers)
+        .collect()
+}
+
+/// Binds every label to the ROM address of the instruction following it,
+/// on top of the predefined symbols.
+pub fn resolve_labels(
+    instructions: &[(usize, Instruction)],
+) -> Result<HashMap<String, u16>, AssembleError> {
+    let mut symbols = predefined_symbols();
+    let mut address = 0;
+    for (line, instruction) in instructions {
+        match instruction {
+            Instruction::Label(label) if symbols.insert(label.clone(), address).is_some() => {
+                return Err(AssembleError {
+                    line: *line,
+                    kind: AssembleErrorKind::DuplicateLabel(label.clone()),
+                });
+            }
+            x if x.is_code() => address += 1,
+            _ => {}
+        }
+    }
+    Ok(symbols)
+}
+
+/// Binds labels as [`resolve_labels`] does, then every other symbol to a
+/// variable address from 16 in the order it first appears.
+pub fn resolve_symbols(
+    instructions: &[(usize, Instruction)],
+) -> Result<HashMap<String, u16>, AssembleError> {
+    let mut symbols = resolve_labels(instructions)?;
+    let mut next_variable = FIRST_VARIABLE;
+    for (_, instruction) in instructions {
+        match instruction {
+            Instruction::A(symbol) if !symbol.starts_with(|c: char| c.is_ascii_digit()) => {
+                symbols.entry(symbol.clone()).or_insert_with(|| {
+                    next_variable += 1;
+                    next_variable - 1
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(symbols)
+}
+
+/// Resolves labels and variables and encodes every instruction to a 16-bit
+/// machine word. Lines are only used to report errors.
+pub fn assemble(instructions: &[(usize, Instruction)]) -> Result<Vec<u16>, AssembleError> {
+    let symbols = resolve_symbols(instructions)?;
+    let mut words = Vec::new();
+    for (line, instruction) in instructions {
+        let error = |kind| AssembleError { line: *line, kind };
+        let word = match instruction {
+            Instruction::A(value) if value.starts_with(|c: char| c.is_ascii_digit()) => {
+                match value.parse::<u16>() {
+                    Ok(value) if value < 0x8000 => value,
+                    _ => return Err(error(AssembleErrorKind::ConstantTooLarge(value.clone()))),
+                }
+            }
+            Instruction::A(symbol) => symbols[symbol],
+            Instruction::C { dest, comp, jump } => {
+                let comp_bits = encode_comp(comp)
+                    .ok_or_else(|| error(AssembleErrorKind::UnknownComp(comp.clone())))?;
+                let dest_bits = match dest {
+                    Some(dest) => encode_dest(dest)
+                        .ok_or_else(|| error(AssembleErrorKind::UnknownDest(dest.clone())))?,
+                    None => 0,
+                };
+                let jump_bits = match jump {
+                    Some(jump) => encode_jump(jump)
+                        .ok_or_else(|| error(AssembleErrorKind::UnknownJump(jump.clone())))?,
+                    None => 0,
+                };
+                0b111 << 13 | comp_bits << 6 | dest_bits << 3 | jump_bits
+            }
+            Instruction::Label(_) | Instruction::Comment(_) => continue,
+        };
+        words.push(word);
+    }
+    Ok(words)
+}
+
+/// Parses and assembles the text of an `.asm` file.
+pub fn assemble_source(source: &str) -> Result<Vec<u16>, AssembleError> {
+    assemble(&parse(source)?)
+}
+
+/// The `.hack` text format: one 16-character binary word per line.
+pub fn to_hack(words: &[u16]) -> String {
+    words.iter().map(|word| format!("{word:016b}\n")).collect()
+}
+
+/// Raw machine code, two big-endian bytes per word.
+pub fn to_binary(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_be_bytes()).collect()
+}
+
+// the `a` bit followed by `c1`-`c6`
+fn encode_comp(comp: &str) -> Option<u16> {
+    let a = if comp.contains('M') { 1 << 6 } else { 0 };
+    let bits = match comp.replace('M', "A").as_str() {
+        "0" => 0b101010,
+        "1" => 0b111111,
+        "-1" => 0b111010,
+        "D" => 0b001100,
+        "A" => 0b110000,
+        "!D" => 0b001101,
+        "!A" => 0b110001,
+        "-D" => 0b001111,
+        "-A" => 0b110011,
+        "D+1" | "1+D" => 0b011111,
+        "A+1" | "1+A" => 0b110111,
+        "D-1" => 0b001110,
+        "A-1" => 0b110010,
+        "D+A" | "A+D" => 0b000010,
+        "D-A" => 0b010011,
+        "A-D" => 0b000111,
+        "D&A" | "A&D" => 0b000000,
+        "D|A" | "A|D" => 0b010101,
+        _ => return None,
+    };
+    Some(a | bits)
+}
+
+fn encode_dest(dest: &str) -> Option<u16> {
+    let mut bits = 0;
+    for register in dest.chars() {
+        let bit = match register {
+            'A' => 0b100,
+            'D' => 0b010,
+            'M' => 0b001,
+            _ => return None,
+        };
+        if bits & bit != 0 {
+            return None;
+        }
+        bits |= bit;
+    }
+    Some(bits)
+}
+
+fn encode_jump(jump: &str) -> Option<u16> {
+    let bits = match jump {
+        "JGT" => 0b001,
+        "JEQ" => 0b010,
+        "JGE" => 0b011,
+        "JLT" => 0b100,
+        "JNE" => 0b101,
+        "JLE" => 0b110,
+        "JMP" => 0b111,
+        _ => return None,
+    };
+    Some(bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble_source, to_binary, to_hack, AssembleError, AssembleErrorKind};
+
+    #[test]
+    fn assembles_labels_variables_and_predefined_symbols() {
+        let source = "// loop forever\n@i\nM=1\n(LOOP)\n@LOOP\n0;JMP // spin\n@R13\nAM=M-1\n@SCREEN\nD;JGE\n@j\n";
+        let words = assemble_source(source).unwrap();
+        assert_eq!(
+            to_hack(&words),
+            "0000000000010000\n\
+             1110111111001000\n\
+             0000000000000010\n\
+             1110101010000111\n\
+             0000000000001101\n\
+             1111110010101000\n\
+             0100000000000000\n\
+             1110001100000011\n\
+             0000000000010001\n"
+        );
+        assert_eq!(to_binary(&words[..2]), vec![0x00, 0x10, 0xEF, 0xC8]);
+    }
+
+    #[test]
+    fn reports_invalid_instructions() {
+        let error = |line, kind| Err(AssembleError { line, kind });
+        assert_eq!(
+            assemble_source("@1\nD=D*A\n"),
+            error(2, AssembleErrorKind::UnknownComp(String::from("D*A")))
+        );
+        assert_eq!(
+            assemble_source("@32768\n"),
+            error(
+                1,
+                AssembleErrorKind::ConstantTooLarge(String::from("32768"))
+            )
+        );
+        assert_eq!(
+            assemble_source("(X)\n\n(X)\n"),
+            error(3, AssembleErrorKind::DuplicateLabel(String::from("X")))
+        );
+        assert_eq!(
+            assemble_source("0;JMPS\n"),
+            error(1, AssembleErrorKind::UnknownJump(String::from("JMPS")))
+        );
+    }
+}
@@ -0,0 +1,180 @@
+//! The interface between the translator and the code it generates. The
+//! driver walks a program and calls one method per VM operation, so the same
+//! program can be written as Hack assembly or any other output.
+
+use std::io;
+
+use crate::{
+    ir::{Module, Program},
+    parser::{ArithmeticLogical, Command, Segment},
+};
+
+/// Generates code for VM operations, in the order the driver walks them.
+/// Labels are passed as written in the VM code along with the function they
+/// are scoped to, `None` for top-level code.
+pub trait Backend {
+    /// What [`Backend::finish`] returns once everything is written.
+    type Output;
+
+    /// Called before the commands of each file.
+    fn start_module(&mut self, module: &Module) -> io::Result<()>;
+
+    /// Called before each command, function declarations included.
+    fn start_command(&mut self, _command: &Command) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_arithmetic(&mut self, op: ArithmeticLogical) -> io::Result<()>;
+    fn write_push(&mut self, segment: Segment, index: usize) -> io::Result<()>;
+    /// `segment` is `constant` only in programs that skipped the validator.
+    fn write_pop(&mut self, segment: Segment, index: usize) -> io::Result<()>;
+    fn write_label(&mut self, label: &str, function: Option<&str>) -> io::Result<()>;
+    fn write_goto(&mut self, label: &str, function: Option<&str>) -> io::Result<()>;
+    fn write_if(&mut self, label: &str, function: Option<&str>) -> io::Result<()>;
+    fn write_function(&mut self, name: &str, n_vars: usize) -> io::Result<()>;
+    fn write_call(&mut self, name: &str, n_args: usize) -> io::Result<()>;
+    fn write_return(&mut self) -> io::Result<()>;
+
+    /// Writes anything that goes after the last command and hands back the
+    /// output.
+    fn finish(self) -> io::Result<Self::Output>
+    where
+        Self: Sized;
+}
+
+/// Writes every command of an already checked `program` to `backend`, file by
+/// file, top-level code first.
+pub fn write_program<B: Backend>(backend: &mut B, program: &Program) -> io::Result<()> {
+    for module in &program.modules {
+        backend.start_module(module)?;
+        for statement in &module.top_level {
+            write_command(backend, &statement.command, None)?;
+        }
+        for function in &module.functions {
+            let name = Some(function.name.as_str());
+            let declaration = Command::Function(function.name.clone(), function.n_vars);
+            write_command(backend, &declaration, name)?;
+            for statement in &function.body {
+                write_command(backend, &statement.command, name)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Writes `command`, found in `function`, to `backend`.
+pub fn write_command<B: Backend>(
+    backend: &mut B,
+    command: &Command,
+    function: Option<&str>,
+) -> io::Result<()> {
+    backend.start_command(command)?;
+    match command {
+        Command::ArithmeticLogical(op) => backend.write_arithmetic(*op),
+        Command::Push(segment, index) => backend.write_push(*segment, *index),
+        Command::Pop(segment, index) => backend.write_pop(*segment, *index),
+        Command::Label(label) => backend.write_label(label, function),
+        Command::Goto(label) => backend.write_goto(label, function),
+        Command::If(label) => backend.write_if(label, function),
+        Command::Function(name, n_vars) => backend.write_function(name, *n_vars),
+        Command::Call(name, n_args) => backend.write_call(name, *n_args),
+        Command::Return => backend.write_return(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::{write_program, Backend};
+    use crate::{
+        code_writer::HackAsmBackend,
+        ir::Module,
+        parser::{ArithmeticLogical, Segment},
+        translator::{parse_program, Options, StaticNaming},
+    };
+
+    // writes the VM code back out, labels qualified by their function
+    #[derive(Default)]
+    struct Vm(String);
+
+    impl Vm {
+        fn line(&mut self, line: String) -> io::Result<()> {
+            self.0.push_str(&line);
+            self.0.push('\n');
+            Ok(())
+        }
+    }
+
+    impl Backend for Vm {
+        type Output = String;
+
+        fn start_module(&mut self, module: &Module) -> io::Result<()> {
+            self.line(format!("// {}", module.file))
+        }
+        fn write_arithmetic(&mut self, op: ArithmeticLogical) -> io::Result<()> {
+            self.line(op.to_string())
+        }
+        fn write_push(&mut self, segment: Segment, index: usize) -> io::Result<()> {
+            self.line(format!("push {segment} {index}"))
+        }
+        fn write_pop(&mut self, segment: Segment, index: usize) -> io::Result<()> {
+            self.line(format!("pop {segment} {index}"))
+        }
+        fn write_label(&mut self, label: &str, function: Option<&str>) -> io::Result<()> {
+            self.line(format!("label {}${label}", function.unwrap_or("")))
+        }
+        fn write_goto(&mut self, label: &str, function: Option<&str>) -> io::Result<()> {
+            self.line(format!("goto {}${label}", function.unwrap_or("")))
+        }
+        fn write_if(&mut self, label: &str, function: Option<&str>) -> io::Result<()> {
+            self.line(format!("if-goto {}${label}", function.unwrap_or("")))
+        }
+        fn write_function(&mut self, name: &str, n_vars: usize) -> io::Result<()> {
+            self.line(format!("function {name} {n_vars}"))
+        }
+        fn write_call(&mut self, name: &str, n_args: usize) -> io::Result<()> {
+            self.line(format!("call {name} {n_args}"))
+        }
+        fn write_return(&mut self) -> io::Result<()> {
+            self.line(String::from("return"))
+        }
+        fn finish(self) -> io::Result<String> {
+            Ok(self.0)
+        }
+    }
+
+    #[test]
+    fn drives_any_backend() {
+        let sources = [
+            (
+                "Sys.vm",
+                "function Sys.init 0\ncall Main.f 0\nlabel END\ngoto END\n",
+            ),
+            (
+                "Main.vm",
+                "push constant 1\nfunction Main.f 1\npush local 0\nnot\nreturn\n",
+            ),
+        ];
+        let (program, diagnostics) = parse_program(&sources, StaticNaming::Stem);
+        assert!(!diagnostics.has_errors());
+        let mut backend = Vm::default();
+        write_program(&mut backend, &program).unwrap();
+        assert_eq!(
+            backend.finish().unwrap(),
+            "// Sys.vm\nfunction Sys.init 0\ncall Main.f 0\nlabel Sys.init$END\n\
+             goto Sys.init$END\n// Main.vm\npush constant 1\nfunction Main.f 1\n\
+             push local 0\nnot\nreturn\n"
+        );
+    }
+
+    #[test]
+    fn rejects_what_cannot_be_written() {
+        let mut backend = HackAsmBackend::new(Vec::new(), &Options::default()).unwrap();
+        let err = backend.write_pop(Segment::Constant, 1).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        let err = backend.write_call("Main.f g", 0).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(backend.write_call("Main.f", 0).is_ok());
+    }
+}
@@ -1,331 +1,594 @@
-use std::fs::File;
-use std::io::Error;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{self, BufWriter, Write};
 
+use crate::asm::Instruction;
+use crate::backend::Backend;
+use crate::ir::Module;
+use crate::linker::ENTRY_FUNCTION;
+use crate::optimizer;
 use crate::parser::ArithmeticLogical;
 use crate::parser::Command;
+use crate::parser::Segment;
+use crate::translator::{BootstrapMode, Options, StaticNaming};
+
+/// Where the entries of a memory segment live.
+enum Base {
+    /// `constant` has no backing memory, the index is the value itself.
+    Immediate,
+    /// `static` entries are assembler variables, one symbol per index.
+    Symbol(String),
+    /// `pointer` and `temp` are fixed RAM ranges starting at the given address.
+    Fixed(&'static str),
+    /// `argument`, `local`, `this` and `that` start at the address held by the
+    /// given pointer register.
+    Indirect(&'static str),
+}
 
-pub struct CodeWriter {
-    file: File,
-    file_name: String,
+/// The default [`Backend`], writing Hack assembly.
+pub struct HackAsmBackend<W: Write> {
+    out: BufWriter<W>,
+    pending: Option<Vec<Instruction>>, // held back for the optimizer until finish()
+    shared_runtime: bool,
+    runtime_at_end: bool, // no bootstrap to put the shared routines in
+    static_naming: StaticNaming,
+    file_name: String,      // path of the module being written, naming its statics
     logical_counter: usize, // guarantees unique label for logical op jumps
     call_counter: usize,    // guarantees unique return labels
 }
 
-impl CodeWriter {
-    pub fn build(path: PathBuf) -> Result<CodeWriter, Error> {
-        let file = File::create(&path)?;
-        let mut code_writer = CodeWriter {
-            file,
-            file_name: String::from(path.file_stem().and_then(|x| x.to_str()).unwrap()),
+impl<W: Write> HackAsmBackend<W> {
+    /// Creates a writer emitting assembly to `out`, starting with the bootstrap
+    /// code `options` ask for.
+    pub fn new(out: W, options: &Options) -> io::Result<HackAsmBackend<W>> {
+        let mut backend = HackAsmBackend {
+            out: BufWriter::new(out),
+            pending: options.optimize.then(Vec::new),
+            shared_runtime: options.shared_runtime,
+            runtime_at_end: options.shared_runtime && options.bootstrap == BootstrapMode::None,
+            static_naming: options.static_naming,
+            file_name: String::new(),
             logical_counter: 0,
             call_counter: 0,
         };
 
-        code_writer.write_bootstrap();
-
-        Ok(code_writer)
-    }
-
-    fn write_bootstrap(&mut self) {
-        self.writeln("// bootstrap");
-        self.writeln("@256");
-        self.writeln("D=A");
-        self.writeln("@SP");
-        self.writeln("M=D");
-        self.write_call("Sys.init", 0);
-    }
-
-    pub fn write_comment(&mut self, command: &Command) {
-        self.writeln(&format!("// {command}"));
-    }
-
-    pub fn set_file_name(&mut self, file_name: String) {
-        self.file_name = file_name
-    }
-
-    pub fn write_label(&mut self, label: &str) {
-        self.writeln(&format!("({label})"));
-    }
-
-    pub fn write_goto(&mut self, label: &str) {
-        self.writeln(&format!("@{label}"));
-        self.writeln(&format!("0;JMP"));
-    }
+        backend.write_bootstrap(&options.bootstrap)?;
 
-    pub fn write_if(&mut self, label: &str) {
-        self.pop_to_d();
-        self.writeln(&format!("@{label}"));
-        self.writeln(&format!("D;JNE"));
+        Ok(backend)
     }
 
-    pub fn write_arithmetic(&mut self, command: Command) {
-        let command = match command {
-            Command::ArithmeticLogical(arithmetic_logical) => arithmetic_logical,
-            _ => return,
+    fn write_bootstrap(&mut self, mode: &BootstrapMode) -> io::Result<()> {
+        let (pointers, entry) = match mode {
+            BootstrapMode::None => return Ok(()),
+            BootstrapMode::Standard => (vec![("SP", 256)], ENTRY_FUNCTION),
+            BootstrapMode::Custom(bootstrap) => (
+                vec![
+                    ("SP", bootstrap.sp),
+                    ("LCL", bootstrap.lcl),
+                    ("ARG", bootstrap.arg),
+                    ("THIS", bootstrap.this),
+                    ("THAT", bootstrap.that),
+                ],
+                bootstrap.entry.as_str(),
+            ),
         };
-
-        match command {
-            ArithmeticLogical::Add => self.binary_op("+"),
-            ArithmeticLogical::Sub => self.binary_op("-"),
-            ArithmeticLogical::Neg => self.unary_op("-"),
-            ArithmeticLogical::Eq => self.cmp("EQ"),
-            ArithmeticLogical::Gt => self.cmp("GT"),
-            ArithmeticLogical::Lt => self.cmp("LT"),
-            ArithmeticLogical::And => self.binary_op("&"),
-            ArithmeticLogical::Or => self.binary_op("|"),
-            ArithmeticLogical::Not => self.unary_op("!"),
+        self.writeln("// bootstrap")?;
+        for (pointer, value) in pointers {
+            self.writeln(&format!("@{value}"))?;
+            self.writeln("D=A")?;
+            self.writeln(&format!("@{pointer}"))?;
+            self.writeln("M=D")?;
+        }
+        let ret_label = self.write_call_jump(entry, 0)?;
+        // nothing falls through into the shared routines, they are only jumped to
+        if self.shared_runtime {
+            self.writeln("// runtime")?;
+            self.write_runtime()?;
         }
+        self.label(&ret_label)?;
+        Ok(())
     }
 
-    pub fn write_push_pop(&mut self, command: Command) {
-        match command {
-            Command::Push(segment, index) => {
-                self.set_a(segment, index);
-                if segment == "constant" {
-                    self.writeln("D=A");
-                } else {
-                    self.writeln("D=M"); // store segment[index]
-                }
-                self.push_d();
-            }
-            Command::Pop(segment, index) => {
-                self.set_a(segment, index);
-                self.writeln("D=A"); //  store address of segment[index]
-
-                self.writeln("@R13");
-                self.writeln("M=D"); // store &segment[index] to @R13
-
-                self.pop_to_d();
-
-                // store stack value to segment[index]
-                self.writeln("@R13");
-                self.writeln("A=M");
-                self.writeln("M=D");
-            } // no-op
-            _ => {}
-        };
+    // one copy of the calling convention and comparisons that every site jumps to
+    fn write_runtime(&mut self) -> io::Result<()> {
+        // D = return address, R13 = function, R14 = n_args
+        self.writeln("($$call)")?;
+        self.writeln("@SP")?;
+        self.writeln("A=M")?;
+        self.writeln("M=D")?;
+        for pointer in ["LCL", "ARG", "THIS", "THAT"] {
+            self.writeln(&format!("@{pointer}"))?;
+            self.writeln("D=M")?;
+            self.writeln("@SP")?;
+            self.writeln("AM=M+1")?;
+            self.writeln("M=D")?;
+        }
+        self.writeln("@SP")?;
+        self.writeln("MD=M+1")?;
+        self.writeln("@LCL")?;
+        self.writeln("M=D")?;
+        self.writeln("@5")?;
+        self.writeln("D=D-A")?;
+        self.writeln("@R14")?;
+        self.writeln("D=D-M")?;
+        self.writeln("@ARG")?;
+        self.writeln("M=D")?;
+        self.writeln("@R13")?;
+        self.writeln("A=M")?;
+        self.writeln("0;JMP")?;
+
+        self.writeln("($$return)")?;
+        self.write_return_inline()?;
+
+        // R15 = return address
+        for op in ["EQ", "GT", "LT"] {
+            let name = op.to_lowercase();
+            self.writeln(&format!("($${name})"))?;
+            self.pop_to_d()?;
+            self.write_difference(op, &format!("$${name}"))?;
+            self.writeln("@SP")?;
+            self.writeln("A=M-1")?;
+            self.writeln("M=-1")?;
+            self.writeln(&format!("@$${name}.end"))?;
+            self.writeln(&format!("D;J{op}"))?;
+            self.writeln("@SP")?;
+            self.writeln("A=M-1")?;
+            self.writeln("M=0")?;
+            self.writeln(&format!("($${name}.end)"))?;
+            self.writeln("@R15")?;
+            self.writeln("A=M")?;
+            self.writeln("0;JMP")?;
+        }
+        Ok(())
     }
 
-    pub fn write_function(&mut self, function_name: &str, n_vars: usize) {
-        self.writeln(&format!("({function_name})"));
-        // zeroes function's local segment before control transfers to it
-        for _ in 0..n_vars {
-            // todo: optimize and set 0 directly to M
-            self.writeln(&format!("D=0"));
-            self.push_d();
-        }
+    fn label(&mut self, label: &str) -> io::Result<()> {
+        self.writeln(&format!("({label})"))
     }
 
-    pub fn write_call(&mut self, function_name: &str, n_args: usize) {
+    // everything of a call up to the jump into the callee, returns the label
+    // the callee comes back to
+    fn write_call_jump(&mut self, function_name: &str, n_args: usize) -> io::Result<String> {
         let ret_label = format!("{function_name}$ret.{}", self.call_counter);
         self.call_counter += 1;
+        if self.shared_runtime {
+            self.writeln(&format!("@{function_name}"))?;
+            self.writeln("D=A")?;
+            self.writeln("@R13")?;
+            self.writeln("M=D")?;
+            self.writeln(&format!("@{n_args}"))?;
+            self.writeln("D=A")?;
+            self.writeln("@R14")?;
+            self.writeln("M=D")?;
+            self.writeln(&format!("@{ret_label}"))?;
+            self.writeln("D=A")?;
+            self.writeln("@$$call")?;
+            self.writeln("0;JMP")?;
+            return Ok(ret_label);
+        }
+
         // push return address
-        self.writeln(&format!("@{ret_label}"));
-        self.writeln("D=A");
-        self.push_d();
+        self.writeln(&format!("@{ret_label}"))?;
+        self.writeln("D=A")?;
+        self.push_d()?;
 
         // push LCL
-        self.writeln("@LCL");
-        self.writeln("D=M");
-        self.push_d();
+        self.writeln("@LCL")?;
+        self.writeln("D=M")?;
+        self.push_d()?;
 
         // push ARG
-        self.writeln("@ARG");
-        self.writeln("D=M");
-        self.push_d();
+        self.writeln("@ARG")?;
+        self.writeln("D=M")?;
+        self.push_d()?;
 
         // push THIS
-        self.writeln("@THIS");
-        self.writeln("D=M");
-        self.push_d();
+        self.writeln("@THIS")?;
+        self.writeln("D=M")?;
+        self.push_d()?;
 
         // push THAT
-        self.writeln("@THAT");
-        self.writeln("D=M");
-        self.push_d();
+        self.writeln("@THAT")?;
+        self.writeln("D=M")?;
+        self.push_d()?;
 
-        self.writeln("@SP");
-        self.writeln("D=M");
+        self.writeln("@SP")?;
+        self.writeln("D=M")?;
 
-        self.writeln("@LCL");
-        self.writeln("M=D");
+        self.writeln("@LCL")?;
+        self.writeln("M=D")?;
 
         // compute ARG = SP-5-n_args
-        self.writeln("@5");
-        self.writeln("D=D-A");
-        self.writeln(&format!("@{n_args}"));
-        self.writeln("D=D-A");
-        self.writeln("@ARG");
-        self.writeln("M=D");
-
-        self.writeln(&format!("@{function_name}"));
-        self.writeln(&format!("0;JMP"));
-        self.writeln(&format!("({ret_label})"));
+        self.writeln("@5")?;
+        self.writeln("D=D-A")?;
+        self.writeln(&format!("@{n_args}"))?;
+        self.writeln("D=D-A")?;
+        self.writeln("@ARG")?;
+        self.writeln("M=D")?;
+
+        self.writeln(&format!("@{function_name}"))?;
+        self.writeln("0;JMP")?;
+        Ok(ret_label)
     }
 
-    pub fn write_return(&mut self) {
+    fn write_return_inline(&mut self) -> io::Result<()> {
         // frame = LCL
-        self.writeln("@LCL");
-        self.writeln("D=M");
-        self.writeln("@R13");
-        self.writeln("M=D");
+        self.writeln("@LCL")?;
+        self.writeln("D=M")?;
+        self.writeln("@R13")?;
+        self.writeln("M=D")?;
 
         // retAddr = *(frame-5)
-        self.writeln("@R13");
-        self.writeln("D=M");
-        self.writeln("@5");
-        self.writeln("A=D-A");
-        self.writeln("D=M");
-        self.writeln("@R14");
-        self.writeln("M=D");
+        self.writeln("@R13")?;
+        self.writeln("D=M")?;
+        self.writeln("@5")?;
+        self.writeln("A=D-A")?;
+        self.writeln("D=M")?;
+        self.writeln("@R14")?;
+        self.writeln("M=D")?;
 
         // *ARG = pop()
-        self.pop_to_d();
-        self.writeln("@ARG");
-        self.writeln("A=M");
-        self.writeln("M=D");
+        self.pop_to_d()?;
+        self.writeln("@ARG")?;
+        self.writeln("A=M")?;
+        self.writeln("M=D")?;
 
         // SP = ARG+1
-        self.writeln("D=A+1");
-        self.writeln("@SP");
-        self.writeln("M=D");
+        self.writeln("D=A+1")?;
+        self.writeln("@SP")?;
+        self.writeln("M=D")?;
 
         // THAT = *(frame-1)
-        self.writeln("@R13");
-        self.writeln("A=M-1");
-        self.writeln("D=M");
-        self.writeln("@THAT");
-        self.writeln("M=D");
+        self.writeln("@R13")?;
+        self.writeln("A=M-1")?;
+        self.writeln("D=M")?;
+        self.writeln("@THAT")?;
+        self.writeln("M=D")?;
 
         // THIS = *(frame-2)
-        self.writeln("@R13");
-        self.writeln("D=M");
-        self.writeln("@2");
-        self.writeln("A=D-A");
-        self.writeln("D=M");
-        self.writeln("@THIS");
-        self.writeln("M=D");
+        self.writeln("@R13")?;
+        self.writeln("D=M")?;
+        self.writeln("@2")?;
+        self.writeln("A=D-A")?;
+        self.writeln("D=M")?;
+        self.writeln("@THIS")?;
+        self.writeln("M=D")?;
 
         // ARG = *(frame-3)
-        self.writeln("@R13");
-        self.writeln("D=M");
-        self.writeln("@3");
-        self.writeln("A=D-A");
-        self.writeln("D=M");
-        self.writeln("@ARG");
-        self.writeln("M=D");
+        self.writeln("@R13")?;
+        self.writeln("D=M")?;
+        self.writeln("@3")?;
+        self.writeln("A=D-A")?;
+        self.writeln("D=M")?;
+        self.writeln("@ARG")?;
+        self.writeln("M=D")?;
 
         // LCL = *(frame-4)
-        self.writeln("@R13");
-        self.writeln("D=M");
-        self.writeln("@4");
-        self.writeln("A=D-A");
-        self.writeln("D=M");
-        self.writeln("@LCL");
-        self.writeln("M=D");
+        self.writeln("@R13")?;
+        self.writeln("D=M")?;
+        self.writeln("@4")?;
+        self.writeln("A=D-A")?;
+        self.writeln("D=M")?;
+        self.writeln("@LCL")?;
+        self.writeln("M=D")?;
 
         // goto retAddr
-        self.writeln("@R14");
-        self.writeln("A=M");
-        self.writeln("0;JMP");
+        self.writeln("@R14")?;
+        self.writeln("A=M")?;
+        self.writeln("0;JMP")?;
+        Ok(())
     }
 
     // sets a to address of segment[index]
-    fn set_a(&mut self, segment: &str, index: usize) {
-        if segment == "constant" {
-            self.writeln(&format!("@{index}"));
-        } else {
-            let addr = self.segment_to_addr(segment, index);
-            // todo: optimize by only adding when index > 0
-            self.writeln(&format!("@{index}"));
-            self.writeln("D=A");
-            self.writeln(&format!("@{addr}"));
-            match segment {
-                "temp" | "pointer" => self.writeln("A=A+D"),
-                "static" => {}
-                _ => self.writeln("A=M+D"),
+    fn set_a(&mut self, segment: Segment, index: usize) -> io::Result<()> {
+        // todo: optimize by only adding when index > 0
+        match self.segment_base(segment, index) {
+            Base::Immediate => self.writeln(&format!("@{index}"))?,
+            Base::Symbol(symbol) => {
+                self.writeln(&format!("@{index}"))?;
+                self.writeln("D=A")?;
+                self.writeln(&format!("@{symbol}"))?;
+            }
+            Base::Fixed(addr) => {
+                self.writeln(&format!("@{index}"))?;
+                self.writeln("D=A")?;
+                self.writeln(&format!("@{addr}"))?;
+                self.writeln("A=A+D")?;
+            }
+            Base::Indirect(pointer) => {
+                self.writeln(&format!("@{index}"))?;
+                self.writeln("D=A")?;
+                self.writeln(&format!("@{pointer}"))?;
+                self.writeln("A=M+D")?;
             }
         }
+        Ok(())
     }
 
-    fn push_d(&mut self) {
-        self.writeln("@SP");
-        self.writeln("A=M");
-        self.writeln("M=D");
-        self.increment_sp();
+    fn push_d(&mut self) -> io::Result<()> {
+        self.writeln("@SP")?;
+        self.writeln("A=M")?;
+        self.writeln("M=D")?;
+        self.increment_sp()?;
+        Ok(())
     }
 
-    fn pop_to_d(&mut self) {
-        self.decrement_sp();
-        self.writeln("A=M");
-        self.writeln("D=M");
+    fn pop_to_d(&mut self) -> io::Result<()> {
+        self.decrement_sp()?;
+        self.writeln("A=M")?;
+        self.writeln("D=M")?;
+        Ok(())
     }
 
-    fn increment_sp(&mut self) {
-        self.writeln("@SP");
-        self.writeln("M=M+1");
+    fn increment_sp(&mut self) -> io::Result<()> {
+        self.writeln("@SP")?;
+        self.writeln("M=M+1")?;
+        Ok(())
     }
 
-    fn decrement_sp(&mut self) {
-        self.writeln("@SP");
-        self.writeln("M=M-1");
+    fn decrement_sp(&mut self) -> io::Result<()> {
+        self.writeln("@SP")?;
+        self.writeln("M=M-1")?;
+        Ok(())
     }
 
-    fn unary_op(&mut self, op: &str) {
-        self.pop_to_d();
-        self.writeln(&format!("D={op}D"));
-        self.push_d();
+    fn unary_op(&mut self, op: &str) -> io::Result<()> {
+        self.pop_to_d()?;
+        self.writeln(&format!("D={op}D"))?;
+        self.push_d()?;
+        Ok(())
     }
 
-    fn binary_op(&mut self, op: &str) {
-        self.pop_to_d();
-        self.writeln("@R13");
-        self.writeln("M=D");
-        self.pop_to_d();
-        self.writeln("@R13");
-        self.writeln(&format!("D=D{op}M"));
-        self.push_d();
+    fn binary_op(&mut self, op: &str) -> io::Result<()> {
+        self.pop_to_d()?;
+        self.writeln("@R13")?;
+        self.writeln("M=D")?;
+        self.pop_to_d()?;
+        self.writeln("@R13")?;
+        self.writeln(&format!("D=D{op}M"))?;
+        self.push_d()?;
+        Ok(())
     }
 
-    fn cmp(&mut self, op: &str) {
+    fn cmp(&mut self, op: &str) -> io::Result<()> {
         let cmp = &format!("CMP.{}", self.logical_counter);
         let end = &format!("END.{}", self.logical_counter);
-        self.pop_to_d();
-        self.writeln("@R13");
-        self.writeln("M=D");
-        self.pop_to_d();
-        self.writeln("@R13");
-        self.writeln("D=D-M");
-
-        self.writeln(&format!("@{cmp}"));
-        self.writeln(&format!("D;J{op}"));
-        self.writeln("D=0");
-        self.writeln(&format!("@{end}"));
-        self.writeln("0;JMP");
-
-        self.writeln(&format!("({cmp})"));
-        self.writeln("D=-1");
-        self.writeln(&format!("({end})"));
-        self.push_d();
+        if self.shared_runtime {
+            self.writeln(&format!("@{end}"))?;
+            self.writeln("D=A")?;
+            self.writeln("@R15")?;
+            self.writeln("M=D")?;
+            self.writeln(&format!("@$${}", op.to_lowercase()))?;
+            self.writeln("0;JMP")?;
+            self.writeln(&format!("({end})"))?;
+            self.logical_counter += 1;
+            return Ok(());
+        }
+        self.pop_to_d()?;
+        self.write_difference(op, cmp)?;
+
+        self.writeln(&format!("@{cmp}"))?;
+        self.writeln(&format!("D;J{op}"))?;
+        self.writeln("D=0")?;
+        self.writeln(&format!("@{end}"))?;
+        self.writeln("0;JMP")?;
+
+        self.writeln(&format!("({cmp})"))?;
+        self.writeln("D=-1")?;
+        self.writeln(&format!("({end})"))?;
+        self.writeln("@SP")?;
+        self.writeln("A=M-1")?;
+        self.writeln("M=D")?;
         self.logical_counter += 1;
+        Ok(())
+    }
+
+    // with y in D and x on top of the stack, sets D to a value that compares
+    // to 0 as x compares to y. That is x - y, except for gt and lt when the
+    // signs differ and the subtraction could overflow: then it is x | 1, which
+    // has the sign of x and is never 0. Clobbers R13.
+    fn write_difference(&mut self, op: &str, label: &str) -> io::Result<()> {
+        if op == "EQ" {
+            self.writeln("@SP")?;
+            self.writeln("A=M-1")?;
+            self.writeln("D=M-D")?;
+            return Ok(());
+        }
+        self.writeln("@R13")?;
+        self.writeln("M=D")?;
+        self.writeln("@SP")?;
+        self.writeln("A=M-1")?;
+        self.writeln("D=M")?;
+        self.writeln(&format!("@{label}.neg"))?;
+        self.writeln("D;JLT")?;
+        // x >= 0
+        self.writeln("@R13")?;
+        self.writeln("D=M")?;
+        self.writeln(&format!("@{label}.sign"))?;
+        self.writeln("D;JLT")?;
+
+        self.writeln(&format!("({label}.sub)"))?;
+        self.writeln("@SP")?;
+        self.writeln("A=M-1")?;
+        self.writeln("D=M")?;
+        self.writeln("@R13")?;
+        self.writeln("D=D-M")?;
+        self.writeln(&format!("@{label}.done"))?;
+        self.writeln("0;JMP")?;
+
+        // x < 0
+        self.writeln(&format!("({label}.neg)"))?;
+        self.writeln("@R13")?;
+        self.writeln("D=M")?;
+        self.writeln(&format!("@{label}.sub"))?;
+        self.writeln("D;JLT")?;
+
+        self.writeln(&format!("({label}.sign)"))?;
+        self.writeln("@SP")?;
+        self.writeln("A=M-1")?;
+        self.writeln("D=M")?;
+        self.writeln("@1")?;
+        self.writeln("D=D|A")?;
+        self.writeln(&format!("({label}.done)"))?;
+        Ok(())
     }
 
-    fn segment_to_addr(&mut self, segment: &str, index: usize) -> String {
+    fn segment_base(&self, segment: Segment, index: usize) -> Base {
         match segment {
-            "argument" => "ARG".to_owned(),
-            "local" => "LCL".to_owned(),
-            "static" => format!("{}.{}", self.file_name, index),
-            "this" => "THIS".to_owned(),
-            "that" => "THAT".to_owned(),
-            "pointer" => "THIS".to_owned(),
-            "temp" => format!("{}", 5),
-            _ => String::new(),
+            Segment::Constant => Base::Immediate,
+            Segment::Static => Base::Symbol(self.static_naming.symbol(&self.file_name, index)),
+            Segment::Argument => Base::Indirect("ARG"),
+            Segment::Local => Base::Indirect("LCL"),
+            Segment::This => Base::Indirect("THIS"),
+            Segment::That => Base::Indirect("THAT"),
+            Segment::Pointer => Base::Fixed("THIS"),
+            Segment::Temp => Base::Fixed("5"),
         }
     }
 
-    fn writeln(&mut self, str: &str) {
-        let _ = self.file.write_all(format!("{}\n", str).as_bytes());
+    // names in programs built without the parser may not make an instruction
+    fn writeln(&mut self, line: &str) -> io::Result<()> {
+        let instruction = line
+            .parse()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        self.emit(instruction)
+    }
+
+    fn emit(&mut self, instruction: Instruction) -> io::Result<()> {
+        match &mut self.pending {
+            Some(pending) => {
+                pending.push(instruction);
+                Ok(())
+            }
+            None => writeln!(self.out, "{instruction}"),
+        }
     }
 }
+
+impl<W: Write> Backend for HackAsmBackend<W> {
+    type Output = W;
+
+    fn start_module(&mut self, module: &Module) -> io::Result<()> {
+        self.file_name = module.file.clone();
+        Ok(())
+    }
+
+    // the comment marks where the code of each command starts, for the
+    // differential tests, source maps and listings
+    fn start_command(&mut self, command: &Command) -> io::Result<()> {
+        self.writeln(&format!("// {command}"))
+    }
+
+    fn write_arithmetic(&mut self, op: ArithmeticLogical) -> io::Result<()> {
+        match op {
+            ArithmeticLogical::Add => self.binary_op("+"),
+            ArithmeticLogical::Sub => self.binary_op("-"),
+            ArithmeticLogical::Neg => self.unary_op("-"),
+            ArithmeticLogical::Eq => self.cmp("EQ"),
+            ArithmeticLogical::Gt => self.cmp("GT"),
+            ArithmeticLogical::Lt => self.cmp("LT"),
+            ArithmeticLogical::And => self.binary_op("&"),
+            ArithmeticLogical::Or => self.binary_op("|"),
+            ArithmeticLogical::Not => self.unary_op("!"),
+        }
+    }
+
+    fn write_push(&mut self, segment: Segment, index: usize) -> io::Result<()> {
+        self.set_a(segment, index)?;
+        match segment {
+            Segment::Constant => self.writeln("D=A")?,
+            Segment::Argument
+            | Segment::Local
+            | Segment::Static
+            | Segment::This
+            | Segment::That
+            | Segment::Pointer
+            | Segment::Temp => self.writeln("D=M")?, // store segment[index]
+        }
+        self.push_d()
+    }
+
+    fn write_pop(&mut self, segment: Segment, index: usize) -> io::Result<()> {
+        if segment == Segment::Constant {
+            // rejected by validate::validate, but programs can be built without it
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot translate `pop constant {index}`: constant is not addressable"),
+            ));
+        }
+        self.set_a(segment, index)?;
+        self.writeln("D=A")?; //  store address of segment[index]
+
+        self.writeln("@R13")?;
+        self.writeln("M=D")?; // store &segment[index] to @R13
+
+        self.pop_to_d()?;
+
+        // store stack value to segment[index]
+        self.writeln("@R13")?;
+        self.writeln("A=M")?;
+        self.writeln("M=D")
+    }
+
+    fn write_label(&mut self, label: &str, function: Option<&str>) -> io::Result<()> {
+        self.label(&full_label(label, function))
+    }
+
+    fn write_goto(&mut self, label: &str, function: Option<&str>) -> io::Result<()> {
+        self.writeln(&format!("@{}", full_label(label, function)))?;
+        self.writeln("0;JMP")
+    }
+
+    fn write_if(&mut self, label: &str, function: Option<&str>) -> io::Result<()> {
+        self.pop_to_d()?;
+        self.writeln(&format!("@{}", full_label(label, function)))?;
+        self.writeln("D;JNE")
+    }
+
+    fn write_function(&mut self, function_name: &str, n_vars: usize) -> io::Result<()> {
+        self.label(function_name)?;
+        // zeroes function's local segment before control transfers to it
+        for _ in 0..n_vars {
+            // todo: optimize and set 0 directly to M
+            self.writeln("D=0")?;
+            self.push_d()?;
+        }
+        Ok(())
+    }
+
+    fn write_call(&mut self, function_name: &str, n_args: usize) -> io::Result<()> {
+        let ret_label = self.write_call_jump(function_name, n_args)?;
+        self.label(&ret_label)
+    }
+
+    fn write_return(&mut self) -> io::Result<()> {
+        if self.shared_runtime {
+            self.writeln("@$$return")?;
+            self.writeln("0;JMP")?;
+            return Ok(());
+        }
+        self.write_return_inline()
+    }
+
+    /// Flushes any buffered output and returns the underlying writer.
+    fn finish(mut self) -> io::Result<W> {
+        // without a bootstrap the program would run on into the shared
+        // routines, so it halts first
+        if self.runtime_at_end {
+            self.writeln("// runtime")?;
+            self.writeln("($$halt)")?;
+            self.writeln("@$$halt")?;
+            self.writeln("0;JMP")?;
+            self.write_runtime()?;
+        }
+        if let Some(pending) = self.pending.take() {
+            for instruction in optimizer::optimize(pending) {
+                writeln!(self.out, "{instruction}")?;
+            }
+        }
+        self.out.into_inner().map_err(|err| err.into_error())
+    }
+}
+
+// VM labels are scoped to their function: `label` in `function` is `function$label`
+fn full_label(label: &str, function: Option<&str>) -> String {
+    format!("{}${label}", function.unwrap_or(""))
+}
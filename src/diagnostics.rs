@@ -0,0 +1,244 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+};
+
+use crate::{
+    linker::{LinkError, LinkErrorKind},
+    parser::{ParseError, ParseErrorKind, Span},
+    validate::{ValidationError, ValidationErrorKind},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Level::Error => write!(f, "error"),
+            Level::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A single error or warning tied to a location in a `.vm` file, or to the
+/// whole program when `file` is empty.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub level: Level,
+    pub code: &'static str,
+    pub message: String,
+    pub file: String,
+    pub span: Span,
+}
+
+impl From<ParseError> for Diagnostic {
+    fn from(err: ParseError) -> Self {
+        let code = match err.kind {
+            ParseErrorKind::UnknownCommand(_) => "E0001",
+            ParseErrorKind::BadSegment(_) => "E0002",
+            ParseErrorKind::MissingOperand(_) => "E0003",
+            ParseErrorKind::NonNumericIndex { .. } => "E0004",
+            ParseErrorKind::TrailingTokens(_) => "E0005",
+        };
+        Diagnostic {
+            level: Level::Error,
+            code,
+            message: err.kind.to_string(),
+            file: err.file,
+            span: err.span,
+        }
+    }
+}
+
+impl From<ValidationError> for Diagnostic {
+    fn from(err: ValidationError) -> Self {
+        let code = match err.kind {
+            ValidationErrorKind::PopConstant => "E0101",
+            ValidationErrorKind::IndexOutOfRange { .. } => "E0102",
+            ValidationErrorKind::ConstantTooLarge(_) => "E0103",
+        };
+        Diagnostic {
+            level: Level::Error,
+            code,
+            message: err.kind.to_string(),
+            file: err.file,
+            span: err.span,
+        }
+    }
+}
+
+impl From<LinkError> for Diagnostic {
+    fn from(err: LinkError) -> Self {
+        let level = if err.is_warning() {
+            Level::Warning
+        } else {
+            Level::Error
+        };
+        let code = match err.kind {
+            LinkErrorKind::UndefinedLabel { .. } => "E0201",
+            LinkErrorKind::DuplicateLabel { .. } => "E0202",
+            LinkErrorKind::DuplicateFunction(_) => "E0203",
+            LinkErrorKind::UndefinedFunction(_) => "E0204",
+            LinkErrorKind::UnreachableFunction(_) => "W0201",
+            LinkErrorKind::InvalidStaticPrefix(_) => "E0205",
+            LinkErrorKind::StaticPrefixCollision { .. } => "E0206",
+            LinkErrorKind::InvalidSymbol(_) => "E0207",
+            LinkErrorKind::ReservedSymbol(_) => "E0208",
+            LinkErrorKind::UndefinedEntry(_) => "E0209",
+        };
+        Diagnostic {
+            level,
+            code,
+            message: err.kind.to_string(),
+            file: err.file,
+            span: err.span,
+        }
+    }
+}
+
+/// Collects diagnostics across every translated file so they can be reported
+/// together, rendered with the offending source line.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+    sources: HashMap<String, String>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics::default()
+    }
+
+    /// Registers the contents of `file` so its lines can be quoted when rendering.
+    pub fn add_source(&mut self, file: &str, source: &str) {
+        self.sources
+            .insert(String::from(file), String::from(source));
+    }
+
+    pub fn push(&mut self, diagnostic: impl Into<Diagnostic>) {
+        self.diagnostics.push(diagnostic.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.error_count() > 0
+    }
+
+    pub fn error_count(&self) -> usize {
+        self.iter().filter(|x| x.level == Level::Error).count()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    fn render(&self, f: &mut fmt::Formatter<'_>, diagnostic: &Diagnostic) -> fmt::Result {
+        let Diagnostic {
+            level,
+            code,
+            message,
+            file,
+            span,
+        } = diagnostic;
+        writeln!(f, "{level}[{code}]: {message}")?;
+        if file.is_empty() {
+            return writeln!(f);
+        }
+
+        let gutter = " ".repeat(span.line.to_string().len());
+        writeln!(f, "{gutter}--> {file}:{}:{}", span.line, span.column)?;
+
+        let line = self
+            .sources
+            .get(file)
+            .and_then(|source| source.lines().nth(span.line - 1));
+        if let Some(line) = line {
+            // tabs are expanded so the caret lines up with the quoted source
+            let width = |c: char| if c == '\t' { 4 } else { 1 };
+            let padding: usize = line.chars().take(span.column - 1).map(width).sum();
+            let carets: usize = line
+                .chars()
+                .skip(span.column - 1)
+                .take(span.len)
+                .map(width)
+                .sum();
+
+            writeln!(f, "{gutter} |")?;
+            writeln!(f, "{} | {}", span.line, line.replace('\t', "    "))?;
+            writeln!(
+                f,
+                "{gutter} | {}{}",
+                " ".repeat(padding),
+                "^".repeat(carets.max(1))
+            )?;
+        }
+        writeln!(f)
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diagnostic in &self.diagnostics {
+            self.render(f, diagnostic)?;
+        }
+        let warnings = match self.len() - self.error_count() {
+            0 => String::new(),
+            1 => String::from("1 warning emitted"),
+            n => format!("{n} warnings emitted"),
+        };
+        match (self.error_count(), warnings.is_empty()) {
+            (0, true) => Ok(()),
+            (0, false) => writeln!(f, "warning: {warnings}"),
+            (1, true) => writeln!(f, "error: aborting due to 1 previous error"),
+            (n, true) => writeln!(f, "error: aborting due to {n} previous errors"),
+            (1, false) => writeln!(f, "error: aborting due to 1 previous error; {warnings}"),
+            (n, false) => writeln!(f, "error: aborting due to {n} previous errors; {warnings}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Diagnostics;
+    use crate::parser::Parser;
+
+    #[test]
+    fn renders_source_snippet_and_summary() {
+        let source = "push constant 1\n\tpop locl 2\nfoo\n";
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.add_source("Foo.vm", source);
+        for result in Parser::build("Foo.vm", source).unwrap() {
+            if let Err(err) = result {
+                diagnostics.push(err);
+            }
+        }
+
+        assert_eq!(
+            diagnostics.to_string(),
+            "error[E0002]: invalid segment `locl`\n \
+             --> Foo.vm:2:6\n  \
+             |\n\
+             2 |     pop locl 2\n  \
+             |         ^^^^\n\
+             \n\
+             error[E0001]: unknown command `foo`\n \
+             --> Foo.vm:3:1\n  \
+             |\n\
+             3 | foo\n  \
+             | ^^^\n\
+             \n\
+             error: aborting due to 2 previous errors\n"
+        );
+    }
+}
@@ -0,0 +1,422 @@
+//! Runs a program on the [`Interpreter`] and, translated by `HackAsmBackend`,
+//! on the [`Cpu`] side by side, comparing the RAM of both after every VM
+//! command to find the first command the translated code gets wrong.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    path::Path,
+};
+
+use crate::{
+    asm::Instruction,
+    assembler::{self, AssembleError},
+    emulator::{Cpu, EmulatorError, MEMORY_SIZE},
+    interpreter::Interpreter,
+    ir::Program,
+    parser::Command,
+    translator::{self, BootstrapMode, Options},
+};
+
+/// Ticks the translated code of a single command may take to reach the next
+/// one. Calls and returns are the longest at well under a hundred.
+const TICK_LIMIT: usize = 10_000;
+
+const REGISTERS: [&str; 5] = ["SP", "LCL", "ARG", "THIS", "THAT"];
+const TEMP: usize = 5;
+/// `R13`-`R15` are scratch registers of the translated code.
+const SCRATCH: usize = 13;
+const STACK: usize = 256;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Divergence {
+    /// Number of VM commands executed, including the one that diverged.
+    pub step: usize,
+    /// File and command that caused the divergence, `None` for the bootstrap.
+    pub command: Option<(String, Command)>,
+    pub kind: DivergenceKind,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DivergenceKind {
+    /// The translated code never got to the ROM address of the next command.
+    Stalled { expected_pc: u16 },
+    /// A RAM word differs, `expected` being the interpreter's value.
+    Ram {
+        name: String,
+        expected: i16,
+        actual: i16,
+    },
+}
+
+impl Display for DivergenceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DivergenceKind::Stalled { expected_pc } => write!(
+                f,
+                "translated code did not reach the next command at ROM address {expected_pc}"
+            ),
+            DivergenceKind::Ram {
+                name,
+                expected,
+                actual,
+            } => write!(f, "{name} is {actual}, expected {expected}"),
+        }
+    }
+}
+
+impl Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.command {
+            Some((file, command)) => write!(f, "step {} ({file}: `{command}`)", self.step)?,
+            None => write!(f, "step {} (bootstrap)", self.step)?,
+        }
+        write!(f, ": {}", self.kind)
+    }
+}
+
+impl Error for Divergence {}
+
+/// Why a differential run stopped short of a result.
+#[derive(Debug, PartialEq, Eq)]
+pub enum DifferentialError {
+    /// The translated code does not assemble.
+    Assemble(AssembleError),
+    /// The assembled code does not load on the CPU.
+    Emulator(EmulatorError),
+    Divergence(Divergence),
+}
+
+impl Display for DifferentialError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DifferentialError::Assemble(err) => write!(f, "generated assembly: {err}"),
+            DifferentialError::Emulator(err) => write!(f, "{err}"),
+            DifferentialError::Divergence(divergence) => write!(f, "{divergence}"),
+        }
+    }
+}
+
+impl Error for DifferentialError {}
+
+impl From<AssembleError> for DifferentialError {
+    fn from(err: AssembleError) -> Self {
+        DifferentialError::Assemble(err)
+    }
+}
+
+impl From<EmulatorError> for DifferentialError {
+    fn from(err: EmulatorError) -> Self {
+        DifferentialError::Emulator(err)
+    }
+}
+
+impl From<Divergence> for DifferentialError {
+    fn from(divergence: Divergence) -> Self {
+        DifferentialError::Divergence(divergence)
+    }
+}
+
+/// A program loaded on both the interpreter and the CPU.
+pub struct Differential {
+    vm: Interpreter,
+    cpu: Cpu,
+    bootstrap: BootstrapMode,
+    /// ROM address of the code of every command, plus the end of the program.
+    starts: Vec<u16>,
+    /// Interpreter address, CPU address and name of every static.
+    statics: Vec<(usize, usize, String)>,
+    steps: usize,
+}
+
+impl Differential {
+    /// Loads a linked `program`. The optimizer merges the code of neighbouring
+    /// commands, so the program is always translated without it. Fails if the
+    /// translated code does not assemble or fit in ROM.
+    pub fn new(program: &Program, options: &Options) -> Result<Differential, DifferentialError> {
+        let options = Options {
+            optimize: false,
+            ..options.clone()
+        };
+        let asm = translator::write_program(program, &options, Vec::new())
+            .expect("Expected writing to memory to succeed");
+
+        // every command starts with a comment, as does the bootstrap code
+        let mut instructions = Vec::new();
+        let mut starts = Vec::new();
+        let mut address = 0;
+        for (i, line) in String::from_utf8_lossy(&asm).lines().enumerate() {
+            let instruction: Instruction = line
+                .parse()
+                .expect("Expected HackAsmBackend to emit valid assembly");
+            match &instruction {
+                Instruction::Comment(x) if x == "bootstrap" || x == "runtime" => {}
+                Instruction::Comment(_) => starts.push(address),
+                x if x.is_code() => address += 1,
+                _ => {}
+            }
+            instructions.push((i + 1, instruction));
+        }
+
+        let symbols = assembler::resolve_symbols(&instructions)?;
+        // the shared routines may follow the end of the commands
+        starts.push(symbols.get("$$halt").copied().unwrap_or(address));
+        let cpu = Cpu::new(&assembler::assemble(&instructions)?)?;
+
+        let mut vm = Interpreter::new(program);
+        vm.set_return_addresses(starts.clone());
+        let mut statics: Vec<(usize, usize, String)> = vm
+            .statics()
+            .map(|(module, index, address)| {
+                let file = &program.modules[module].file;
+                let symbol = options.static_naming.symbol(file, index);
+                let file_name = Path::new(file)
+                    .file_name()
+                    .and_then(|x| x.to_str())
+                    .expect("Expected file_name() successfully");
+                let name = format!("static {index} of {file_name}");
+                (address, usize::from(symbols[&symbol]), name)
+            })
+            .collect();
+        statics.sort();
+
+        Ok(Differential {
+            bootstrap: options.bootstrap,
+            vm,
+            cpu,
+            starts,
+            statics,
+            steps: 0,
+        })
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    /// Writes `value` to `address` on both sides.
+    pub fn poke(&mut self, address: usize, value: i16) {
+        self.vm.ram_mut()[address] = value;
+        self.cpu.ram_mut()[address] = value;
+    }
+
+    /// Runs the bootstrap code up to the entry function. Without bootstrap
+    /// code, both sides start at the first command with only SP set.
+    pub fn bootstrap(&mut self) -> Result<(), Divergence> {
+        self.vm.bootstrap(&self.bootstrap);
+        if self.bootstrap == BootstrapMode::None {
+            self.cpu.ram_mut()[0] = STACK as i16;
+            self.compare(None)
+        } else {
+            self.sync(None, true)
+        }
+    }
+
+    /// Executes the next command on both sides and compares them. Returns
+    /// `false` without doing anything once the program halted or returns
+    /// from a frame it did not create.
+    pub fn step(&mut self) -> Result<bool, Divergence> {
+        while let Some((_, Command::Label(_))) = self.vm.current() {
+            self.vm.set_pc(self.vm.pc() + 1);
+        }
+        if self.vm.is_halted() || self.returns_outside() {
+            return Ok(false);
+        }
+        let index = self.vm.pc();
+        let (file, command) = self
+            .vm
+            .current()
+            .map(|(file, command)| (String::from(file), command.clone()))
+            .expect("Expected a command before the end of the program");
+        self.vm.step();
+        self.steps += 1;
+        let has_code = self.starts[index] != self.starts[index + 1];
+        self.sync(Some((file, command)), has_code).map(|_| true)
+    }
+
+    /// Steps until the program halts or `max_steps` commands have executed,
+    /// returning the number of commands executed.
+    pub fn run(&mut self, max_steps: usize) -> Result<usize, Divergence> {
+        for steps in 0..max_steps {
+            if !self.step()? {
+                return Ok(steps);
+            }
+        }
+        Ok(max_steps)
+    }
+
+    // whether the next command is a `return` to an address that is not the
+    // code of any command
+    fn returns_outside(&self) -> bool {
+        if !matches!(self.vm.current(), Some((_, Command::Return))) {
+            return false;
+        }
+        let ram = self.vm.ram();
+        let frame = usize::from(ram[1].wrapping_sub(5) as u16) % MEMORY_SIZE;
+        self.vm.return_target(ram[frame]).is_none()
+    }
+
+    // ticks the CPU until it reaches the code of the interpreter's next
+    // command, then compares the RAM of both
+    fn sync(&mut self, command: Option<(String, Command)>, tick: bool) -> Result<(), Divergence> {
+        let expected_pc = self.starts[self.vm.pc().min(self.starts.len() - 1)];
+        let mut ticks = 0;
+        while self.cpu.pc() != expected_pc || (tick && ticks == 0) {
+            if ticks == TICK_LIMIT {
+                return Err(self.divergence(command, DivergenceKind::Stalled { expected_pc }));
+            }
+            self.cpu.tick();
+            ticks += 1;
+        }
+        self.compare(command)
+    }
+
+    fn compare(&self, command: Option<(String, Command)>) -> Result<(), Divergence> {
+        let (expected, actual) = (self.vm.ram(), self.cpu.ram());
+        // `R13`-`R15` are left out, and statics are matched by symbol since
+        // the assembler may hand out their addresses in a different order
+        let registers = (0..SCRATCH).map(|a| (a, a, None));
+        let statics = self
+            .statics
+            .iter()
+            .map(|(vm, cpu, name)| (*vm, *cpu, Some(name)));
+        // the rest of RAM is compared word by word only once it differs
+        let stack_end = if expected[STACK..] == actual[STACK..] {
+            STACK
+        } else {
+            MEMORY_SIZE
+        };
+        let stack = (STACK..stack_end).map(|a| (a, a, None));
+        for (vm_address, cpu_address, name) in registers.chain(statics).chain(stack) {
+            if expected[vm_address] != actual[cpu_address] {
+                let name = match (name, vm_address) {
+                    (Some(name), _) => name.clone(),
+                    (None, a) if a < TEMP => String::from(REGISTERS[a]),
+                    (None, a) if a < SCRATCH => format!("temp {}", a - TEMP),
+                    (None, a) => format!("RAM[{a}]"),
+                };
+                let kind = DivergenceKind::Ram {
+                    name,
+                    expected: expected[vm_address],
+                    actual: actual[cpu_address],
+                };
+                return Err(self.divergence(command, kind));
+            }
+        }
+        Ok(())
+    }
+
+    fn divergence(&self, command: Option<(String, Command)>, kind: DivergenceKind) -> Divergence {
+        Divergence {
+            step: self.steps,
+            command,
+            kind,
+        }
+    }
+}
+
+/// Bootstraps `program` on both sides and runs it for at most `max_steps`
+/// commands, returning the number of commands executed without divergence.
+pub fn run_differential(
+    program: &Program,
+    options: &Options,
+    max_steps: usize,
+) -> Result<usize, DifferentialError> {
+    let mut differential = Differential::new(program, options)?;
+    differential.bootstrap()?;
+    Ok(differential.run(max_steps)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_differential, Differential, DifferentialError, DivergenceKind};
+    use crate::{
+        emulator::EmulatorError,
+        ir::Program,
+        parser::{Command, Segment},
+        translator::{parse_program, BootstrapMode, StaticNaming},
+        Options,
+    };
+
+    fn load(sources: &[(&str, &str)]) -> Program {
+        let (program, diagnostics) = parse_program(sources, StaticNaming::default());
+        assert!(!diagnostics.has_errors(), "{diagnostics}");
+        program
+    }
+
+    fn fibonacci() -> Program {
+        load(&[
+            (
+                "Sys.vm",
+                "function Sys.init 0\npush constant 6\ncall Main.fib 1\npop static 0\n\
+                 label END\ngoto END\n",
+            ),
+            (
+                "Main.vm",
+                "function Main.fib 1\npush argument 0\npush constant 2\nlt\nif-goto BASE\n\
+                 push argument 0\npush constant 1\nsub\ncall Main.fib 1\npop local 0\n\
+                 push argument 0\npush constant 2\nsub\ncall Main.fib 1\npush local 0\nadd\n\
+                 pop static 1\npush static 1\nreturn\nlabel BASE\npush argument 0\nreturn\n",
+            ),
+        ])
+    }
+
+    #[test]
+    fn agrees_on_calls_statics_and_comparisons() {
+        for shared_runtime in [false, true] {
+            let options = Options {
+                shared_runtime,
+                ..Options::default()
+            };
+            let steps = run_differential(&fibonacci(), &options, 100_000).unwrap();
+            assert!(steps > 100, "{steps}");
+        }
+    }
+
+    #[test]
+    fn reports_the_first_divergence() {
+        let program = load(&[(
+            "Main.vm",
+            "push constant 1\npop static 2\npush static 2\npush constant 3\nadd\n",
+        )]);
+        let options = Options {
+            bootstrap: BootstrapMode::None,
+            ..Options::default()
+        };
+        let mut differential = Differential::new(&program, &options).unwrap();
+        differential.bootstrap().unwrap();
+        assert_eq!(differential.run(3), Ok(3));
+
+        // as if `push constant 3` had also overwritten the value below it
+        differential.cpu_mut().ram_mut()[256] = 7;
+        let divergence = differential.step().unwrap_err();
+        assert_eq!(divergence.step, 4);
+        assert_eq!(
+            divergence.command,
+            Some((String::from("Main.vm"), Command::Push(Segment::Constant, 3)))
+        );
+        assert_eq!(
+            divergence.kind,
+            DivergenceKind::Ram {
+                name: String::from("RAM[256]"),
+                expected: 1,
+                actual: 7,
+            }
+        );
+    }
+
+    #[test]
+    fn reports_code_that_does_not_load() {
+        let program = load(&[("Main.vm", &"push constant 1\npop temp 0\n".repeat(3000))]);
+        let options = Options {
+            bootstrap: BootstrapMode::None,
+            ..Options::default()
+        };
+        assert!(matches!(
+            run_differential(&program, &options, 10),
+            Err(DifferentialError::Emulator(EmulatorError::ProgramTooLarge(
+                _
+            )))
+        ));
+    }
+}
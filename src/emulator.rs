@@ -0,0 +1,279 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+/// Number of words in both the instruction and the data memory.
+pub const MEMORY_SIZE: usize = 32768;
+
+/// `0;JMP`
+const JUMP: u16 = 0b1110_1010_1000_0111;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EmulatorError {
+    ProgramTooLarge(usize),
+    /// A `.hack` line that is not 16 binary digits.
+    InvalidWord {
+        line: usize,
+        text: String,
+    },
+}
+
+impl Display for EmulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmulatorError::ProgramTooLarge(len) => write!(
+                f,
+                "program of {len} instructions does not fit in ROM ({MEMORY_SIZE} words)"
+            ),
+            EmulatorError::InvalidWord { line, text } => {
+                write!(f, "line {line}: `{text}` is not a 16-bit binary word")
+            }
+        }
+    }
+}
+
+impl Error for EmulatorError {}
+
+/// Reads the `.hack` text format, one 16-character binary word per line.
+pub fn parse_hack(text: &str) -> Result<Vec<u16>, EmulatorError> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            let line = line.trim();
+            match u16::from_str_radix(line, 2) {
+                Ok(word) if line.len() == 16 => Ok(word),
+                _ => Err(EmulatorError::InvalidWord {
+                    line: i + 1,
+                    text: String::from(line),
+                }),
+            }
+        })
+        .collect()
+}
+
+/// The Hack computer: 32K words of ROM and RAM and the A, D and PC registers.
+pub struct Cpu {
+    rom: Vec<u16>,
+    len: usize,
+    ram: Vec<i16>,
+    a: i16,
+    d: i16,
+    pc: u16,
+}
+
+impl Cpu {
+    /// Loads `program` into ROM with RAM and registers zeroed.
+    pub fn new(program: &[u16]) -> Result<Cpu, EmulatorError> {
+        if program.len() > MEMORY_SIZE {
+            return Err(EmulatorError::ProgramTooLarge(program.len()));
+        }
+        let mut rom = vec![0; MEMORY_SIZE];
+        rom[..program.len()].copy_from_slice(program);
+        Ok(Cpu {
+            rom,
+            len: program.len(),
+            ram: vec![0; MEMORY_SIZE],
+            a: 0,
+            d: 0,
+            pc: 0,
+        })
+    }
+
+    pub fn ram(&self) -> &[i16] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [i16] {
+        &mut self.ram
+    }
+
+    pub fn a(&self) -> i16 {
+        self.a
+    }
+
+    pub fn d(&self) -> i16 {
+        self.d
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    /// Whether the program ran off its end or sits in a `(X) @X 0;JMP` loop.
+    pub fn is_halted(&self) -> bool {
+        let pc = usize::from(self.pc);
+        if pc >= self.len {
+            return true;
+        }
+        // `@X` at address X followed by `0;JMP`
+        pc + 1 < self.len && usize::from(self.rom[pc]) == pc && self.rom[pc + 1] == JUMP
+    }
+
+    /// Executes the instruction at PC.
+    pub fn tick(&mut self) {
+        let instruction = self.rom[usize::from(self.pc)];
+        if instruction & 0x8000 == 0 {
+            self.a = instruction as i16;
+            self.pc = (self.pc + 1) % MEMORY_SIZE as u16;
+            return;
+        }
+
+        let address = usize::from(self.a as u16) % MEMORY_SIZE;
+        let y = if instruction & 0x1000 != 0 {
+            self.ram[address]
+        } else {
+            self.a
+        };
+        let out = alu(self.d, y, (instruction >> 6) & 0b111111);
+
+        let target = self.a as u16 % MEMORY_SIZE as u16;
+        if instruction & 0b001000 != 0 {
+            self.ram[address] = out;
+        }
+        if instruction & 0b100000 != 0 {
+            self.a = out;
+        }
+        if instruction & 0b010000 != 0 {
+            self.d = out;
+        }
+
+        let jump = instruction & 0b111;
+        let taken = (jump & 0b100 != 0 && out < 0)
+            || (jump & 0b010 != 0 && out == 0)
+            || (jump & 0b001 != 0 && out > 0);
+        self.pc = if taken {
+            target
+        } else {
+            (self.pc + 1) % MEMORY_SIZE as u16
+        };
+    }
+
+    /// Runs until the program halts or `max_ticks` instructions have executed,
+    /// returning the number of instructions executed.
+    pub fn run(&mut self, max_ticks: usize) -> usize {
+        for ticks in 0..max_ticks {
+            if self.is_halted() {
+                return ticks;
+            }
+            self.tick();
+        }
+        max_ticks
+    }
+}
+
+// the Hack ALU, `bits` are the control bits zx nx zy ny f no
+fn alu(x: i16, y: i16, bits: u16) -> i16 {
+    let bit = |n: u16| bits & (1 << (5 - n)) != 0;
+    let x = if bit(0) { 0 } else { x };
+    let x = if bit(1) { !x } else { x };
+    let y = if bit(2) { 0 } else { y };
+    let y = if bit(3) { !y } else { y };
+    let out = if bit(4) { x.wrapping_add(y) } else { x & y };
+    if bit(5) {
+        !out
+    } else {
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_hack, Cpu, EmulatorError};
+    use crate::assembler::assemble_source;
+
+    fn run(source: &str, ram: &[(usize, i16)]) -> Cpu {
+        let mut cpu = Cpu::new(&assemble_source(source).unwrap()).unwrap();
+        for &(address, value) in ram {
+            cpu.ram_mut()[address] = value;
+        }
+        cpu.run(10_000);
+        assert!(cpu.is_halted());
+        cpu
+    }
+
+    #[test]
+    fn executes_every_computation() {
+        let comps = [
+            ("0", 0),
+            ("1", 1),
+            ("-1", -1),
+            ("D", 12),
+            ("A", 5),
+            ("M", 7),
+            ("!D", !12),
+            ("!A", !5),
+            ("!M", !7),
+            ("-D", -12),
+            ("-A", -5),
+            ("-M", -7),
+            ("D+1", 13),
+            ("A+1", 6),
+            ("M+1", 8),
+            ("D-1", 11),
+            ("A-1", 4),
+            ("M-1", 6),
+            ("D+A", 17),
+            ("D+M", 19),
+            ("D-A", 7),
+            ("D-M", 5),
+            ("A-D", -7),
+            ("M-D", -5),
+            ("D&A", 4),
+            ("D&M", 4),
+            ("D|A", 13),
+            ("D|M", 15),
+        ];
+        for (comp, expected) in comps {
+            let source = format!("@12\nD=A\n@5\nD={comp}\n@100\nM=D\n(END)\n@END\n0;JMP\n");
+            let cpu = run(&source, &[(5, 7)]);
+            assert_eq!(cpu.ram()[100], expected, "{comp}");
+        }
+    }
+
+    #[test]
+    fn executes_jumps_and_destinations() {
+        // sums 1..=10 into RAM[17], then halts
+        let source = "@10\nD=A\n@i\nM=D\n(LOOP)\n@i\nD=M\n@END\nD;JEQ\n@sum\nM=D+M\n@i\nM=M-1\n\
+                      @LOOP\n0;JMP\n(END)\n@END\n0;JMP\n";
+        let cpu = run(source, &[]);
+        assert_eq!(cpu.ram()[17], 55);
+        assert_eq!(cpu.ram()[16], 0);
+
+        let jumps = [
+            ("JGT", 1),
+            ("JEQ", 0),
+            ("JGE", 1),
+            ("JLT", 0),
+            ("JNE", 1),
+            ("JLE", 0),
+            ("JMP", 1),
+        ];
+        for (jump, taken) in jumps {
+            let source = format!(
+                "@TAKEN\nD=1;{jump}\n@END\n0;JMP\n(TAKEN)\n@R0\nAMD=1\n(END)\n@END\n0;JMP\n"
+            );
+            assert_eq!(run(&source, &[]).ram()[0], taken, "{jump}");
+        }
+    }
+
+    #[test]
+    fn parses_hack_text() {
+        assert_eq!(
+            parse_hack("0000000000000010\n1110110000010000\n"),
+            Ok(vec![2, 0b1110110000010000])
+        );
+        assert_eq!(
+            parse_hack("0000\n"),
+            Err(EmulatorError::InvalidWord {
+                line: 1,
+                text: String::from("0000")
+            })
+        );
+    }
+}
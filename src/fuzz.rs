@@ -0,0 +1,715 @@
+//! Generates random well-formed VM programs, checks that their translation
+//! agrees with the [`Interpreter`](crate::interpreter::Interpreter) and
+//! shrinks the ones that do not down to a small reproducer.
+//!
+//! Programs are generated as a tree of [`Statement`]s rather than as text so
+//! that shrinking can remove or simplify parts of them while keeping branches
+//! structured and the stack balanced.
+
+use std::fmt::{self, Display};
+
+use crate::{
+    differential::{Differential, DifferentialError},
+    ir::Program,
+    linker::ENTRY_FUNCTION,
+    parser::{ArithmeticLogical, Segment},
+    translator::{parse_program, Options, StaticNaming},
+};
+
+/// Files the generated functions are spread over, `Sys.vm` holding only
+/// `Sys.init`.
+const MODULES: [&str; 2] = ["Main", "Util"];
+/// Values `SetPointer` points `this` and `that` at, clear of the stack.
+const BASES: [u16; 4] = [3000, 3100, 3200, 3300];
+/// Indexes used for `static`, `this`, `that` and `temp`.
+const INDEXES: usize = 8;
+
+/// Knobs for [`Case::generate`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Functions besides `Sys.init`.
+    pub functions: usize,
+    /// Statements per block, before nested blocks.
+    pub statements: usize,
+    /// How deep `if` and loops nest.
+    pub depth: usize,
+    pub max_args: usize,
+    pub max_vars: usize,
+    pub max_iterations: u16,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            functions: 4,
+            statements: 8,
+            depth: 2,
+            max_args: 3,
+            max_vars: 3,
+            max_iterations: 3,
+        }
+    }
+}
+
+/// A small deterministic generator (SplitMix64), so a seed always gives the
+/// same program.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, `n` being positive.
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Statement {
+    Push(Segment, usize),
+    Pop(Segment, usize),
+    Op(ArithmeticLogical),
+    /// `push constant base` and `pop pointer index`, the only way pointers
+    /// are changed so `this` and `that` never point at the registers.
+    SetPointer(usize, u16),
+    /// Calls the function with the given index, which must come later so
+    /// calls always terminate.
+    Call(usize),
+    /// Pops a condition and runs the first block if it is true, the second
+    /// otherwise.
+    If(Vec<Statement>, Vec<Statement>),
+    /// Runs the block a fixed number of times, counting in a local of its own.
+    Loop(u16, Vec<Statement>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub n_args: usize,
+    /// Locals available to the statements, loop counters come on top.
+    pub n_vars: usize,
+    pub body: Vec<Statement>,
+}
+
+/// A generated program. Function 0 is `Sys.init`, which takes no arguments
+/// and halts in a loop once its body is done. Every other function returns
+/// the single value its body leaves on the stack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Case {
+    pub functions: Vec<Function>,
+}
+
+impl Case {
+    pub fn generate(seed: u64, config: &Config) -> Case {
+        let mut rng = Rng::new(seed);
+        let count = config.functions + 1;
+        let signatures: Vec<(usize, usize)> = (0..count)
+            .map(|i| {
+                let n_args = if i == 0 {
+                    0
+                } else {
+                    rng.below(config.max_args + 1)
+                };
+                (n_args, rng.below(config.max_vars + 1))
+            })
+            .collect();
+        let functions = (0..count)
+            .map(|i| {
+                let mut generator = Generator {
+                    rng: &mut rng,
+                    config,
+                    signatures: &signatures,
+                    function: i,
+                };
+                let (n_args, n_vars) = signatures[i];
+                Function {
+                    n_args,
+                    n_vars,
+                    body: generator.statements(config.depth),
+                }
+            })
+            .collect();
+        let mut case = Case { functions };
+        case.repair();
+        case
+    }
+
+    /// Name of the `index`-th function.
+    pub fn name(&self, index: usize) -> String {
+        match index {
+            0 => String::from(ENTRY_FUNCTION),
+            _ => format!("{}.f{index}", MODULES[index % MODULES.len()]),
+        }
+    }
+
+    /// The program as `(file name, text)` pairs.
+    pub fn sources(&self) -> Vec<(String, String)> {
+        let mut sources = vec![(String::from("Sys.vm"), String::new())];
+        sources.extend(MODULES.iter().map(|x| (format!("{x}.vm"), String::new())));
+        for (index, function) in self.functions.iter().enumerate() {
+            let file = match index {
+                0 => 0,
+                _ => 1 + index % MODULES.len(),
+            };
+            let writer = Writer {
+                case: self,
+                function,
+                loops: 0,
+                labels: 0,
+                text: &mut sources[file].1,
+            };
+            writer.function(index);
+        }
+        sources.retain(|(_, text)| !text.is_empty());
+        sources
+    }
+
+    /// Parses the generated sources, which are always valid.
+    pub fn program(&self) -> Program {
+        let (program, diagnostics) = parse_program(&self.sources(), StaticNaming::default());
+        assert!(
+            !diagnostics.has_errors(),
+            "generated program is invalid:\n{diagnostics}"
+        );
+        program
+    }
+
+    /// Runs the case with [`Differential`] until it halts or `max_steps`
+    /// commands have executed.
+    pub fn check(&self, options: &Options, max_steps: usize) -> Result<usize, DifferentialError> {
+        let mut differential = Differential::new(&self.program(), options)?;
+        differential.bootstrap()?;
+        Ok(differential.run(max_steps)?)
+    }
+
+    /// What shrinking minimizes: the number of functions and statements, then
+    /// the size of the constants, iteration counts and locals.
+    fn cost(&self) -> (usize, usize) {
+        fn cost(block: &[Statement]) -> (usize, usize) {
+            block.iter().fold((0, 0), |(n, sum), x| {
+                let (inner_n, inner_sum) = match x {
+                    Statement::If(then, otherwise) => {
+                        let (a, b) = (cost(then), cost(otherwise));
+                        (a.0 + b.0, a.1 + b.1)
+                    }
+                    Statement::Loop(count, body) => {
+                        let (n, sum) = cost(body);
+                        (n, sum + usize::from(*count))
+                    }
+                    Statement::Push(Segment::Constant, value) => (0, *value),
+                    _ => (0, 0),
+                };
+                (n + 1 + inner_n, sum + inner_sum)
+            })
+        }
+        self.functions
+            .iter()
+            .map(|x| {
+                let (n, sum) = cost(&x.body);
+                (n + 1, sum + x.n_args + x.n_vars)
+            })
+            .fold((0, 0), |a, b| (a.0 + b.0, a.1 + b.1))
+    }
+
+    // Drops statements that are no longer valid after shrinking, such as
+    // calls to removed functions, and balances the stack of every block.
+    fn repair(&mut self) {
+        let signatures: Vec<(usize, usize)> = self
+            .functions
+            .iter()
+            .map(|x| (x.n_args, x.n_vars))
+            .collect();
+        for (index, function) in self.functions.iter_mut().enumerate() {
+            let context = Context {
+                signatures: &signatures,
+                function: index,
+            };
+            let result = usize::from(index != 0);
+            function.body = context.repair(std::mem::take(&mut function.body), result);
+        }
+    }
+
+    /// Smaller variants of the case, each still well-formed.
+    fn candidates(&self) -> Vec<Case> {
+        let mut candidates = Vec::new();
+        for removed in 1..self.functions.len() {
+            let mut case = self.clone();
+            case.functions.remove(removed);
+            for function in &mut case.functions {
+                renumber_calls(&mut function.body, removed);
+            }
+            candidates.push(case);
+        }
+        for (index, function) in self.functions.iter().enumerate() {
+            for body in block_variants(&function.body) {
+                let mut case = self.clone();
+                case.functions[index].body = body;
+                candidates.push(case);
+            }
+            if function.n_vars > 0 {
+                let mut case = self.clone();
+                case.functions[index].n_vars -= 1;
+                candidates.push(case);
+            }
+            if function.n_args > 0 {
+                let mut case = self.clone();
+                case.functions[index].n_args -= 1;
+                candidates.push(case);
+            }
+        }
+        for case in &mut candidates {
+            case.repair();
+        }
+        candidates
+    }
+}
+
+impl Display for Case {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (file, text) in self.sources() {
+            writeln!(f, "// {file}")?;
+            write!(f, "{text}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Repeatedly replaces `case` by a smaller variant for which `fails` still
+/// holds, until no variant does.
+pub fn shrink(case: Case, mut fails: impl FnMut(&Case) -> bool) -> Case {
+    let mut case = case;
+    'shrinking: loop {
+        let cost = case.cost();
+        for candidate in case.candidates() {
+            if candidate.cost() < cost && fails(&candidate) {
+                case = candidate;
+                continue 'shrinking;
+            }
+        }
+        return case;
+    }
+}
+
+/// A generated program the translation got wrong, already shrunk.
+#[derive(Debug)]
+pub struct Failure {
+    pub seed: u64,
+    pub case: Case,
+    pub error: DifferentialError,
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "seed {}: {}", self.seed, self.error)?;
+        write!(f, "{}", self.case)
+    }
+}
+
+/// Checks the programs generated from `cases` consecutive seeds starting at
+/// `seed`, returning the first failure shrunk to a minimal reproducer.
+pub fn fuzz(
+    seed: u64,
+    cases: u64,
+    config: &Config,
+    options: &Options,
+    max_steps: usize,
+) -> Option<Failure> {
+    (seed..seed + cases).find_map(|seed| {
+        let case = Case::generate(seed, config);
+        case.check(options, max_steps).err()?;
+        let case = shrink(case, |x| x.check(options, max_steps).is_err());
+        let error = case.check(options, max_steps).unwrap_err();
+        Some(Failure { seed, case, error })
+    })
+}
+
+struct Generator<'a> {
+    rng: &'a mut Rng,
+    config: &'a Config,
+    signatures: &'a [(usize, usize)],
+    function: usize,
+}
+
+impl Generator<'_> {
+    // statements for a block, keeping track of the stack so that most of
+    // them are valid as generated; `Case::repair` fixes up the rest
+    fn statements(&mut self, depth: usize) -> Vec<Statement> {
+        let (n_args, n_vars) = self.signatures[self.function];
+        let mut block = Vec::new();
+        let mut stack = 0;
+        for _ in 0..self.rng.below(self.config.statements + 1) {
+            let statement = match self.rng.below(12) {
+                0..=2 => self.push(n_args, n_vars),
+                3 | 4 if stack > 0 => self.pop(n_args, n_vars),
+                5 | 6 if stack > 1 => {
+                    let ops = [
+                        ArithmeticLogical::Add,
+                        ArithmeticLogical::Sub,
+                        ArithmeticLogical::Eq,
+                        ArithmeticLogical::Gt,
+                        ArithmeticLogical::Lt,
+                        ArithmeticLogical::And,
+                        ArithmeticLogical::Or,
+                    ];
+                    Statement::Op(ops[self.rng.below(ops.len())])
+                }
+                7 if stack > 0 => {
+                    let ops = [ArithmeticLogical::Neg, ArithmeticLogical::Not];
+                    Statement::Op(ops[self.rng.below(ops.len())])
+                }
+                8 => Statement::SetPointer(self.rng.below(2), BASES[self.rng.below(BASES.len())]),
+                9 if self.function + 1 < self.signatures.len() => {
+                    let later = self.signatures.len() - self.function - 1;
+                    Statement::Call(self.function + 1 + self.rng.below(later))
+                }
+                10 if depth > 0 && stack > 0 => {
+                    Statement::If(self.statements(depth - 1), self.statements(depth - 1))
+                }
+                11 if depth > 0 => {
+                    let count = self.rng.below(usize::from(self.config.max_iterations) + 1);
+                    Statement::Loop(count as u16, self.statements(depth - 1))
+                }
+                _ => self.push(n_args, n_vars),
+            };
+            let (needs, pushes) = effect(&statement, self.signatures);
+            stack = stack.max(needs) - needs + pushes;
+            block.push(statement);
+        }
+        block
+    }
+
+    fn push(&mut self, n_args: usize, n_vars: usize) -> Statement {
+        match self.rng.below(8) {
+            0..=2 => {
+                // mostly small values, with the extremes now and then
+                let value = match self.rng.below(4) {
+                    0 => 0x7FFF - self.rng.below(3),
+                    _ => self.rng.below(100),
+                };
+                Statement::Push(Segment::Constant, value)
+            }
+            3 => Statement::Push(Segment::Pointer, self.rng.below(2)),
+            _ => {
+                let (segment, index) = self.segment(n_args, n_vars);
+                Statement::Push(segment, index)
+            }
+        }
+    }
+
+    fn pop(&mut self, n_args: usize, n_vars: usize) -> Statement {
+        let (segment, index) = self.segment(n_args, n_vars);
+        Statement::Pop(segment, index)
+    }
+
+    // a segment other than `constant` and `pointer` with a valid index
+    fn segment(&mut self, n_args: usize, n_vars: usize) -> (Segment, usize) {
+        let mut segments = vec![Segment::Static, Segment::This, Segment::That, Segment::Temp];
+        if n_args > 0 {
+            segments.push(Segment::Argument);
+        }
+        if n_vars > 0 {
+            segments.push(Segment::Local);
+        }
+        let segment = segments[self.rng.below(segments.len())];
+        let index = match segment {
+            Segment::Argument => self.rng.below(n_args),
+            Segment::Local => self.rng.below(n_vars),
+            _ => self.rng.below(INDEXES),
+        };
+        (segment, index)
+    }
+}
+
+struct Context<'a> {
+    signatures: &'a [(usize, usize)],
+    function: usize,
+}
+
+impl Context<'_> {
+    // drops invalid statements and pushes zeros or pops into `temp 0` so
+    // that no statement underflows the stack and `result` values are left
+    fn repair(&self, block: Vec<Statement>, result: usize) -> Vec<Statement> {
+        let (n_args, n_vars) = self.signatures[self.function];
+        let mut repaired = Vec::new();
+        let mut stack = 0;
+        for statement in block {
+            let statement = match statement {
+                Statement::Push(Segment::Argument, index)
+                | Statement::Pop(Segment::Argument, index)
+                    if index >= n_args =>
+                {
+                    continue
+                }
+                Statement::Push(Segment::Local, index) | Statement::Pop(Segment::Local, index)
+                    if index >= n_vars =>
+                {
+                    continue
+                }
+                Statement::Pop(Segment::Constant | Segment::Pointer, _) => continue,
+                Statement::Call(index)
+                    if index <= self.function || index >= self.signatures.len() =>
+                {
+                    continue
+                }
+                Statement::If(then, otherwise) => {
+                    Statement::If(self.repair(then, 0), self.repair(otherwise, 0))
+                }
+                Statement::Loop(count, body) => Statement::Loop(count, self.repair(body, 0)),
+                x => x,
+            };
+            let (needs, pushes) = effect(&statement, self.signatures);
+            while stack < needs {
+                repaired.push(Statement::Push(Segment::Constant, 0));
+                stack += 1;
+            }
+            stack = stack - needs + pushes;
+            repaired.push(statement);
+        }
+        for _ in stack..result {
+            repaired.push(Statement::Push(Segment::Constant, 0));
+        }
+        for _ in result..stack {
+            repaired.push(Statement::Pop(Segment::Temp, 0));
+        }
+        repaired
+    }
+}
+
+// how many values a statement pops, and how many it then pushes
+fn effect(statement: &Statement, signatures: &[(usize, usize)]) -> (usize, usize) {
+    match statement {
+        Statement::Push(..) => (0, 1),
+        Statement::Pop(..) => (1, 0),
+        Statement::Op(ArithmeticLogical::Neg | ArithmeticLogical::Not) => (1, 1),
+        Statement::Op(_) => (2, 1),
+        Statement::SetPointer(..) | Statement::Loop(..) => (0, 0),
+        Statement::Call(index) => (signatures.get(*index).map_or(0, |x| x.0), 1),
+        Statement::If(..) => (1, 0),
+    }
+}
+
+// removes the calls to the `removed`-th function and moves those to later
+// ones down by one
+fn renumber_calls(block: &mut Vec<Statement>, removed: usize) {
+    block.retain(|x| *x != Statement::Call(removed));
+    for statement in block {
+        match statement {
+            Statement::Call(index) if *index > removed => *index -= 1,
+            Statement::If(then, otherwise) => {
+                renumber_calls(then, removed);
+                renumber_calls(otherwise, removed);
+            }
+            Statement::Loop(_, body) => renumber_calls(body, removed),
+            _ => {}
+        }
+    }
+}
+
+// the block with a run of statements removed, a branch or loop replaced by
+// its body, or a constant made smaller, in every possible way, longest
+// removals first
+fn block_variants(block: &[Statement]) -> Vec<Vec<Statement>> {
+    let mut variants = Vec::new();
+    for len in (1..=block.len()).rev() {
+        for start in 0..=block.len() - len {
+            let mut variant = block[..start].to_vec();
+            variant.extend_from_slice(&block[start + len..]);
+            variants.push(variant);
+        }
+    }
+    let replace = |i: usize, with: Vec<Statement>| {
+        let mut variant = block[..i].to_vec();
+        variant.extend(with);
+        variant.extend_from_slice(&block[i + 1..]);
+        variant
+    };
+    for (i, statement) in block.iter().enumerate() {
+        match statement {
+            Statement::If(then, otherwise) => {
+                for body in [then, otherwise] {
+                    let mut inlined = vec![Statement::Pop(Segment::Temp, 0)];
+                    inlined.extend(body.iter().cloned());
+                    variants.push(replace(i, inlined));
+                }
+                for then in block_variants(then) {
+                    variants.push(replace(i, vec![Statement::If(then, otherwise.clone())]));
+                }
+                for otherwise in block_variants(otherwise) {
+                    variants.push(replace(i, vec![Statement::If(then.clone(), otherwise)]));
+                }
+            }
+            Statement::Loop(count, body) => {
+                variants.push(replace(i, body.clone()));
+                if *count > 0 {
+                    variants.push(replace(i, vec![Statement::Loop(count - 1, body.clone())]));
+                }
+                for body in block_variants(body) {
+                    variants.push(replace(i, vec![Statement::Loop(*count, body)]));
+                }
+            }
+            Statement::Push(Segment::Constant, value) if *value > 0 => {
+                for smaller in [0, value / 2] {
+                    variants.push(replace(
+                        i,
+                        vec![Statement::Push(Segment::Constant, smaller)],
+                    ));
+                }
+            }
+            _ => {}
+        }
+    }
+    variants
+}
+
+struct Writer<'a> {
+    case: &'a Case,
+    function: &'a Function,
+    /// Loop counters handed out so far, each a local after the statements' own.
+    loops: usize,
+    labels: usize,
+    text: &'a mut String,
+}
+
+impl Writer<'_> {
+    fn function(mut self, index: usize) {
+        let n_locals = self.function.n_vars + count_loops(&self.function.body);
+        self.line(format!("function {} {n_locals}", self.case.name(index)));
+        if index == 0 {
+            self.line(format!("push constant {}", BASES[0]));
+            self.line(String::from("pop pointer 0"));
+            self.line(format!("push constant {}", BASES[1]));
+            self.line(String::from("pop pointer 1"));
+        }
+        self.block(&self.function.body);
+        if index == 0 {
+            self.line(String::from("label HALT"));
+            self.line(String::from("goto HALT"));
+        } else {
+            self.line(String::from("return"));
+        }
+    }
+
+    fn block(&mut self, block: &[Statement]) {
+        for statement in block {
+            self.statement(statement);
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Push(segment, index) => self.line(format!("push {segment} {index}")),
+            Statement::Pop(segment, index) => self.line(format!("pop {segment} {index}")),
+            Statement::Op(op) => self.line(format!("{op}")),
+            Statement::SetPointer(index, base) => {
+                self.line(format!("push constant {base}"));
+                self.line(format!("pop pointer {index}"));
+            }
+            Statement::Call(index) => {
+                let n_args = self.case.functions[*index].n_args;
+                self.line(format!("call {} {n_args}", self.case.name(*index)));
+            }
+            Statement::If(then, otherwise) => {
+                let n = self.next_label();
+                self.line(format!("if-goto THEN{n}"));
+                self.block(otherwise);
+                self.line(format!("goto END{n}"));
+                self.line(format!("label THEN{n}"));
+                self.block(then);
+                self.line(format!("label END{n}"));
+            }
+            Statement::Loop(count, body) => {
+                let n = self.next_label();
+                let counter = self.function.n_vars + self.loops;
+                self.loops += 1;
+                self.line(format!("push constant {count}"));
+                self.line(format!("pop local {counter}"));
+                self.line(format!("label LOOP{n}"));
+                self.line(format!("push local {counter}"));
+                self.line(format!("if-goto BODY{n}"));
+                self.line(format!("goto END{n}"));
+                self.line(format!("label BODY{n}"));
+                self.block(body);
+                self.line(format!("push local {counter}"));
+                self.line(String::from("push constant 1"));
+                self.line(String::from("sub"));
+                self.line(format!("pop local {counter}"));
+                self.line(format!("goto LOOP{n}"));
+                self.line(format!("label END{n}"));
+            }
+        }
+    }
+
+    fn next_label(&mut self) -> usize {
+        self.labels += 1;
+        self.labels - 1
+    }
+
+    fn line(&mut self, line: String) {
+        self.text.push_str(&line);
+        self.text.push('\n');
+    }
+}
+
+fn count_loops(block: &[Statement]) -> usize {
+    block
+        .iter()
+        .map(|x| match x {
+            Statement::If(then, otherwise) => count_loops(then) + count_loops(otherwise),
+            Statement::Loop(_, body) => 1 + count_loops(body),
+            _ => 0,
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{shrink, Case, Config, Statement};
+    use crate::parser::{ArithmeticLogical, Segment};
+
+    #[test]
+    fn generates_valid_programs() {
+        for seed in 0..50 {
+            let case = Case::generate(seed, &Config::default());
+            assert_eq!(case, Case::generate(seed, &Config::default()));
+            // panics on invalid programs
+            case.program();
+        }
+    }
+
+    #[test]
+    fn shrinks_to_a_minimal_case() {
+        fn contains_lt(block: &[Statement]) -> bool {
+            block.iter().any(|x| match x {
+                Statement::Op(ArithmeticLogical::Lt) => true,
+                Statement::If(then, otherwise) => contains_lt(then) || contains_lt(otherwise),
+                Statement::Loop(_, body) => contains_lt(body),
+                _ => false,
+            })
+        }
+        let fails = |case: &Case| contains_lt(&case.functions[0].body);
+        let case = (0..)
+            .map(|seed| Case::generate(seed, &Config::default()))
+            .find(|x| fails(x) && x.functions[0].body.len() > 5)
+            .unwrap();
+        let shrunk = shrink(case, fails);
+        assert_eq!(shrunk.functions.len(), 1);
+        assert_eq!(
+            shrunk.functions[0].body,
+            [
+                Statement::Push(Segment::Constant, 0),
+                Statement::Push(Segment::Constant, 0),
+                Statement::Op(ArithmeticLogical::Lt),
+                Statement::Pop(Segment::Temp, 0),
+            ]
+        );
+    }
+}
@@ -0,0 +1,412 @@
+//! Executes VM commands directly, with the same RAM layout as the translated
+//! assembly, to serve as a reference for what `HackAsmBackend` should produce.
+
+use std::collections::HashMap;
+
+use crate::{
+    emulator::MEMORY_SIZE,
+    ir::Program,
+    parser::{ArithmeticLogical, Command, Segment},
+    translator::BootstrapMode,
+};
+
+const SP: usize = 0;
+const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+const TEMP: usize = 5;
+/// First address of the static variables, shared by every file.
+const STATIC: usize = 16;
+const STACK: i16 = 256;
+
+/// A command of the flattened program with its jump or call target resolved.
+struct Line {
+    module: usize,
+    command: Command,
+    target: usize,
+}
+
+/// A VM program loaded for execution. Return addresses pushed by `call` are
+/// indexes into the flattened program, commands numbered in the order
+/// `HackAsmBackend` writes them, unless mapped with `set_return_addresses`.
+pub struct Interpreter {
+    files: Vec<String>,
+    code: Vec<Line>,
+    functions: HashMap<String, usize>,
+    statics: HashMap<(usize, usize), usize>,
+    return_addresses: Vec<u16>,
+    ram: Vec<i16>,
+    pc: usize,
+}
+
+impl Interpreter {
+    /// Loads a linked `program`. Statics get consecutive addresses from 16 in
+    /// the order they first appear, as assembler variables do.
+    pub fn new(program: &Program) -> Interpreter {
+        let mut code = Vec::new();
+        let mut labels = HashMap::new();
+        let mut functions = HashMap::new();
+        let mut statics = HashMap::new();
+        for (module, source) in program.modules.iter().enumerate() {
+            let top_level = source.top_level.iter().map(|x| (None, x.command.clone()));
+            let functions_code = source.functions.iter().flat_map(|f| {
+                let declaration = Command::Function(f.name.clone(), f.n_vars);
+                let body = f
+                    .body
+                    .iter()
+                    .map(|x| (Some(f.name.as_str()), x.command.clone()));
+                [(Some(f.name.as_str()), declaration)]
+                    .into_iter()
+                    .chain(body)
+            });
+            for (function, command) in top_level.chain(functions_code) {
+                match &command {
+                    Command::Label(label) => {
+                        labels.insert(scoped(function, label), code.len());
+                    }
+                    Command::Function(name, _) => {
+                        functions.insert(name.clone(), code.len());
+                    }
+                    Command::Push(Segment::Static, index)
+                    | Command::Pop(Segment::Static, index) => {
+                        let next = STATIC + statics.len();
+                        statics.entry((module, *index)).or_insert(next);
+                    }
+                    _ => {}
+                }
+                code.push((module, function.map(String::from), command));
+            }
+        }
+
+        let code = code
+            .into_iter()
+            .map(|(module, function, command)| {
+                let target = match &command {
+                    Command::Goto(label) | Command::If(label) => {
+                        labels[&scoped(function.as_deref(), label)]
+                    }
+                    Command::Call(name, _) => functions[name],
+                    _ => 0,
+                };
+                Line {
+                    module,
+                    command,
+                    target,
+                }
+            })
+            .collect();
+
+        let mut ram = vec![0; MEMORY_SIZE];
+        ram[SP] = STACK;
+        Interpreter {
+            files: program.modules.iter().map(|x| x.file.clone()).collect(),
+            code,
+            functions,
+            statics,
+            return_addresses: Vec::new(),
+            ram,
+            pc: 0,
+        }
+    }
+
+    /// Sets the pointers and calls the entry function the way the bootstrap
+    /// code written for `mode` does, if the function is defined. Should it
+    /// return, execution continues at the first command.
+    pub fn bootstrap(&mut self, mode: &BootstrapMode) {
+        match mode {
+            BootstrapMode::None => return,
+            BootstrapMode::Standard => self.ram[SP] = STACK,
+            BootstrapMode::Custom(bootstrap) => {
+                let pointers = [bootstrap.sp, bootstrap.lcl, bootstrap.arg];
+                let pointers = pointers.into_iter().chain([bootstrap.this, bootstrap.that]);
+                for (pointer, value) in [SP, LCL, ARG, THIS, THAT].into_iter().zip(pointers) {
+                    self.ram[pointer] = value as i16;
+                }
+            }
+        }
+        if let Some(&entry) = mode.entry().and_then(|x| self.functions.get(x)) {
+            self.call(entry, 0, 0);
+        }
+    }
+
+    /// Pushes `addresses[i]` instead of `i` when a call returns to command
+    /// `i`, with one more entry for the end of the program. Lets frames match
+    /// the translated code, where `addresses` are the ROM addresses of the
+    /// commands. `addresses` must be sorted.
+    pub fn set_return_addresses(&mut self, addresses: Vec<u16>) {
+        self.return_addresses = addresses;
+    }
+
+    /// The command a return address pushed by `call` refers to, if any.
+    pub fn return_target(&self, address: i16) -> Option<usize> {
+        if self.return_addresses.is_empty() {
+            let index = address as u16 as usize;
+            return (index <= self.code.len()).then_some(index);
+        }
+        let address = address as u16;
+        let index = self.return_addresses.partition_point(|&x| x < address);
+        (self.return_addresses.get(index) == Some(&address)).then_some(index)
+    }
+
+    pub fn ram(&self) -> &[i16] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [i16] {
+        &mut self.ram
+    }
+
+    /// Index of the next command to execute.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    /// Index of the `function` command declaring `name`.
+    pub fn function(&self, name: &str) -> Option<usize> {
+        self.functions.get(name).copied()
+    }
+
+    /// RAM address of `static index` in the `module`-th file, if it is used.
+    pub fn static_address(&self, module: usize, index: usize) -> Option<usize> {
+        self.statics.get(&(module, index)).copied()
+    }
+
+    /// Every static used as `(module, index, address)`.
+    pub fn statics(&self) -> impl Iterator<Item = (usize, usize, usize)> + '_ {
+        self.statics
+            .iter()
+            .map(|(&(module, index), &address)| (module, index, address))
+    }
+
+    /// The next command to execute and the file it comes from.
+    pub fn current(&self) -> Option<(&str, &Command)> {
+        let line = self.code.get(self.pc)?;
+        Some((&self.files[line.module], &line.command))
+    }
+
+    /// Whether execution ran off the end of the program or sits in a
+    /// `label X` / `goto X` loop.
+    pub fn is_halted(&self) -> bool {
+        let Some(line) = self.code.get(self.pc) else {
+            return true;
+        };
+        let is_goto = |pc: usize, target: usize| {
+            let goto = self.code.get(pc);
+            goto.is_some_and(|x| matches!(x.command, Command::Goto(_)) && x.target == target)
+        };
+        match line.command {
+            Command::Label(_) => is_goto(self.pc + 1, self.pc),
+            Command::Goto(_) => line.target + 1 == self.pc && is_goto(self.pc, line.target),
+            _ => false,
+        }
+    }
+
+    /// Executes the next command. Labels are skipped rather than counted as a
+    /// step, as in the VM emulator. Returns `false` without doing anything
+    /// when the program counter is past the end of the program.
+    pub fn step(&mut self) -> bool {
+        while matches!(self.current(), Some((_, Command::Label(_)))) {
+            self.pc += 1;
+        }
+        let Some(line) = self.code.get(self.pc) else {
+            return false;
+        };
+        let (module, target) = (line.module, line.target);
+        let command = line.command.clone();
+        self.pc += 1;
+        match command {
+            Command::ArithmeticLogical(op) => self.arithmetic(op),
+            Command::Push(segment, index) => {
+                let value = match segment {
+                    Segment::Constant => index as i16,
+                    _ => self.ram[self.address(module, segment, index)],
+                };
+                self.push(value);
+            }
+            Command::Pop(segment, index) => {
+                let value = self.pop();
+                let address = self.address(module, segment, index);
+                self.ram[address] = value;
+            }
+            Command::Label(_) => unreachable!(),
+            Command::Goto(_) => self.pc = target,
+            Command::If(_) => {
+                if self.pop() != 0 {
+                    self.pc = target;
+                }
+            }
+            Command::Function(_, n_vars) => {
+                for _ in 0..n_vars {
+                    self.push(0);
+                }
+            }
+            Command::Call(_, n_args) => self.call(target, n_args, self.pc),
+            Command::Return => self.ret(),
+        }
+        true
+    }
+
+    /// Runs until the program halts or `max_steps` commands have executed,
+    /// returning the number of commands executed.
+    pub fn run(&mut self, max_steps: usize) -> usize {
+        for steps in 0..max_steps {
+            if self.is_halted() {
+                return steps;
+            }
+            self.step();
+        }
+        max_steps
+    }
+
+    fn call(&mut self, function: usize, n_args: usize, return_address: usize) {
+        let return_address = match self.return_addresses.get(return_address) {
+            Some(&address) => address as i16,
+            None => return_address as i16,
+        };
+        self.push(return_address);
+        for pointer in [LCL, ARG, THIS, THAT] {
+            self.push(self.ram[pointer]);
+        }
+        self.ram[ARG] = self.ram[SP].wrapping_sub(5).wrapping_sub(n_args as i16);
+        self.ram[LCL] = self.ram[SP];
+        self.pc = function;
+    }
+
+    fn ret(&mut self) {
+        let frame = self.ram[LCL];
+        let return_address = self.ram[address(frame.wrapping_sub(5))];
+        let arg = address(self.ram[ARG]);
+        self.ram[arg] = self.pop();
+        self.ram[SP] = self.ram[ARG].wrapping_add(1);
+        for (offset, pointer) in [THAT, THIS, ARG, LCL].into_iter().enumerate() {
+            self.ram[pointer] = self.ram[address(frame.wrapping_sub(1 + offset as i16))];
+        }
+        self.pc = self
+            .return_target(return_address)
+            .unwrap_or(self.code.len());
+    }
+
+    fn arithmetic(&mut self, op: ArithmeticLogical) {
+        let y = self.pop();
+        let result = match op {
+            ArithmeticLogical::Neg => y.wrapping_neg(),
+            ArithmeticLogical::Not => !y,
+            _ => {
+                let x = self.pop();
+                match op {
+                    ArithmeticLogical::Add => x.wrapping_add(y),
+                    ArithmeticLogical::Sub => x.wrapping_sub(y),
+                    ArithmeticLogical::Eq => -i16::from(x == y),
+                    ArithmeticLogical::Gt => -i16::from(x > y),
+                    ArithmeticLogical::Lt => -i16::from(x < y),
+                    ArithmeticLogical::And => x & y,
+                    ArithmeticLogical::Or => x | y,
+                    ArithmeticLogical::Neg | ArithmeticLogical::Not => unreachable!(),
+                }
+            }
+        };
+        self.push(result);
+    }
+
+    fn address(&self, module: usize, segment: Segment, index: usize) -> usize {
+        let indirect = |pointer: usize| address(self.ram[pointer].wrapping_add(index as i16));
+        match segment {
+            Segment::Argument => indirect(ARG),
+            Segment::Local => indirect(LCL),
+            Segment::This => indirect(THIS),
+            Segment::That => indirect(THAT),
+            Segment::Pointer => THIS + index,
+            Segment::Temp => TEMP + index,
+            Segment::Static => self.statics[&(module, index)],
+            Segment::Constant => panic!("constant is not addressable"),
+        }
+    }
+
+    fn push(&mut self, value: i16) {
+        let sp = address(self.ram[SP]);
+        self.ram[sp] = value;
+        self.ram[SP] = self.ram[SP].wrapping_add(1);
+    }
+
+    fn pop(&mut self) -> i16 {
+        self.ram[SP] = self.ram[SP].wrapping_sub(1);
+        self.ram[address(self.ram[SP])]
+    }
+}
+
+// wraps into RAM as the CPU does, so runaway stacks never panic
+fn address(value: i16) -> usize {
+    usize::from(value as u16) % MEMORY_SIZE
+}
+
+fn scoped(function: Option<&str>, label: &str) -> String {
+    format!("{}${label}", function.unwrap_or(""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Interpreter;
+    use crate::translator::{parse_program, BootstrapMode, StaticNaming};
+
+    fn load(sources: &[(&str, &str)]) -> Interpreter {
+        let (program, diagnostics) = parse_program(sources, StaticNaming::default());
+        assert!(!diagnostics.has_errors(), "{diagnostics}");
+        Interpreter::new(&program)
+    }
+
+    #[test]
+    fn executes_arithmetic_and_segments() {
+        let mut vm = load(&[(
+            "Main.vm",
+            "push constant 10\npop local 0\npush constant 7\npush constant 8\nlt\n\
+             push constant 32767\nneg\npush constant 2\ngt\npush constant 3000\npop pointer 1\n\
+             push constant 5\npop that 2\npush that 2\npush local 0\nsub\npop static 3\n",
+        )]);
+        vm.ram_mut()[1] = 300;
+        assert_eq!(vm.run(100), 17);
+        assert!(vm.is_halted());
+        assert_eq!(vm.ram()[0], 258);
+        assert_eq!(vm.ram()[256..258], [-1, 0]);
+        assert_eq!(vm.ram()[300], 10);
+        assert_eq!(vm.ram()[3002], 5);
+        assert_eq!(vm.static_address(0, 3), Some(16));
+        assert_eq!(vm.ram()[16], -5);
+    }
+
+    #[test]
+    fn calls_and_returns_across_files() {
+        let mut vm = load(&[
+            (
+                "Sys.vm",
+                "function Sys.init 0\npush constant 6\ncall Main.fib 1\npop static 0\n\
+                 label END\ngoto END\n",
+            ),
+            (
+                "Main.vm",
+                "function Main.fib 0\npush argument 0\npush constant 2\nlt\nif-goto BASE\n\
+                 push argument 0\npush constant 1\nsub\ncall Main.fib 1\n\
+                 push argument 0\npush constant 2\nsub\ncall Main.fib 1\nadd\nreturn\n\
+                 label BASE\npush argument 0\nreturn\n",
+            ),
+        ]);
+        vm.bootstrap(&BootstrapMode::Standard);
+        vm.run(10_000);
+        assert!(vm.is_halted());
+        assert_eq!(vm.current().map(|(file, _)| file), Some("Sys.vm"));
+        assert_eq!(vm.ram()[0], 261);
+        assert_eq!(vm.ram()[16], 8);
+    }
+
+    #[test]
+    fn runs_away_without_panicking() {
+        let mut vm = load(&[("Sys.vm", "function Sys.init 0\ncall Sys.init 0\n")]);
+        vm.bootstrap(&BootstrapMode::Standard);
+        assert_eq!(vm.run(100_000), 100_000);
+        assert!(!vm.is_halted());
+    }
+}
@@ -0,0 +1,110 @@
+//! Owned representation of a VM program, independent of the source text it
+//! was parsed from. Programs can be built by [`crate::parser::Parser`] or
+//! constructed directly.
+
+use crate::parser::{Span, Statement};
+
+/// A whole VM program, one module per `.vm` file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub modules: Vec<Module>,
+}
+
+/// A single `.vm` file.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Module {
+    /// Path of the file the module was parsed from, used in diagnostics and
+    /// to name its static variables.
+    pub file: String,
+    /// Commands appearing before the first `function` declaration.
+    pub top_level: Vec<Statement>,
+    pub functions: Vec<Function>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Function {
+    pub name: String,
+    pub n_vars: usize,
+    /// Span of the function name in its declaration.
+    pub span: Span,
+    pub body: Vec<Statement>,
+}
+
+impl Program {
+    pub fn new(modules: Vec<Module>) -> Program {
+        Program { modules }
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &Function> {
+        self.modules.iter().flat_map(|x| x.functions.iter())
+    }
+}
+
+impl Module {
+    pub fn new(file: &str) -> Module {
+        Module {
+            file: String::from(file),
+            ..Module::default()
+        }
+    }
+
+    /// Every statement in the module, paired with the name of its enclosing
+    /// function (`None` for top-level code). Function declarations themselves
+    /// are not included.
+    pub fn statements(&self) -> impl Iterator<Item = (Option<&str>, &Statement)> {
+        let top_level = self.top_level.iter().map(|x| (None, x));
+        let functions = self
+            .functions
+            .iter()
+            .flat_map(|f| f.body.iter().map(|x| (Some(f.name.as_str()), x)));
+        top_level.chain(functions)
+    }
+}
+
+impl Function {
+    pub fn new(name: &str, n_vars: usize) -> Function {
+        Function {
+            name: String::from(name),
+            n_vars,
+            ..Function::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Function, Module};
+    use crate::parser::{ArithmeticLogical, Command, Parser, Segment};
+
+    #[test]
+    fn parser_groups_commands_by_function() {
+        let source = "push constant 1\nfunction Foo.bar 2\npush local 0\nfunction Foo.baz 0\nadd\n";
+        let (module, errors) = Parser::build("Foo.vm", source).unwrap().parse_module();
+        assert!(errors.is_empty());
+
+        let mut expected = Module::new("Foo.vm");
+        expected
+            .top_level
+            .push(Command::Push(Segment::Constant, 1).into());
+        let mut bar = Function::new("Foo.bar", 2);
+        bar.body.push(Command::Push(Segment::Local, 0).into());
+        let mut baz = Function::new("Foo.baz", 0);
+        baz.body
+            .push(Command::ArithmeticLogical(ArithmeticLogical::Add).into());
+        expected.functions = vec![bar, baz];
+
+        // spans only exist on parsed statements, so compare the structure
+        let strip = |module: &Module| {
+            module
+                .statements()
+                .map(|(function, statement)| {
+                    (function.map(String::from), statement.command.clone())
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(strip(&module), strip(&expected));
+        assert_eq!(module.functions[0].name, "Foo.bar");
+        assert_eq!(module.functions[0].n_vars, 2);
+        assert_eq!(module.functions[1].span.line, 4);
+    }
+}
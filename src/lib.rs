@@ -1,2 +1,21 @@
+pub mod asm;
+pub mod assembler;
+pub mod backend;
 pub mod code_writer;
+pub mod diagnostics;
+pub mod differential;
+pub mod emulator;
+pub mod fuzz;
+pub mod interpreter;
+pub mod ir;
+pub mod linker;
+pub mod listing;
+pub mod optimizer;
 pub mod parser;
+pub mod script;
+pub mod source_map;
+pub mod symbols;
+pub mod translator;
+pub mod validate;
+
+pub use translator::{translate, BootstrapMode, FileOrder, Options, StaticNaming};
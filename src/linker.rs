@@ -0,0 +1,528 @@
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    fmt::{self, Display},
+};
+
+use crate::{
+    asm::is_symbol,
+    assembler::predefined_symbols,
+    ir::Program,
+    parser::{Command, Segment, Span},
+    translator::{BootstrapMode, StaticNaming},
+};
+
+/// Entry point called by the bootstrap code; reachability is computed from it.
+pub const ENTRY_FUNCTION: &str = "Sys.init";
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct LinkError {
+    /// Empty for errors about the whole program.
+    pub file: String,
+    pub span: Span,
+    pub kind: LinkErrorKind,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum LinkErrorKind {
+    UndefinedLabel { label: String, function: String },
+    DuplicateLabel { label: String, function: String },
+    DuplicateFunction(String),
+    UndefinedFunction(String),
+    UnreachableFunction(String),
+    InvalidStaticPrefix(String),
+    StaticPrefixCollision { prefix: String, file: String },
+    InvalidSymbol(String),
+    ReservedSymbol(String),
+    UndefinedEntry(String),
+}
+
+impl LinkError {
+    /// Unreachable functions still translate fine, so they are only reported as warnings.
+    pub fn is_warning(&self) -> bool {
+        matches!(self.kind, LinkErrorKind::UnreachableFunction(_))
+    }
+}
+
+impl Display for LinkErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkErrorKind::UndefinedLabel { label, function } => {
+                write!(f, "label `{label}` is not defined in {}", scope(function))
+            }
+            LinkErrorKind::DuplicateLabel { label, function } => {
+                write!(f, "label `{label}` is defined twice in {}", scope(function))
+            }
+            LinkErrorKind::DuplicateFunction(name) => {
+                write!(f, "function `{name}` is defined more than once")
+            }
+            LinkErrorKind::UndefinedFunction(name) => {
+                write!(f, "call to undefined function `{name}`")
+            }
+            LinkErrorKind::UnreachableFunction(name) => {
+                write!(
+                    f,
+                    "function `{name}` is never called from `{ENTRY_FUNCTION}`"
+                )
+            }
+            LinkErrorKind::InvalidStaticPrefix(prefix) => {
+                write!(
+                    f,
+                    "statics would be named `{prefix}.i`, which is not a valid symbol"
+                )
+            }
+            LinkErrorKind::StaticPrefixCollision { prefix, file } => {
+                write!(f, "statics are named `{prefix}.i` as in {file}")
+            }
+            LinkErrorKind::InvalidSymbol(name) => {
+                write!(f, "`{name}` is not a valid assembler symbol")
+            }
+            LinkErrorKind::ReservedSymbol(symbol) => {
+                write!(f, "`{symbol}` is reserved for the generated code")
+            }
+            LinkErrorKind::UndefinedEntry(entry) => {
+                write!(f, "bootstrap calls `{entry}`, which is not defined")
+            }
+        }
+    }
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.file.is_empty() {
+            return write!(f, "{}", self.kind);
+        }
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.span.line, self.span.column, self.kind
+        )
+    }
+}
+
+impl Error for LinkError {}
+
+fn scope(function: &str) -> String {
+    if function.is_empty() {
+        String::from("top-level code")
+    } else {
+        format!("function `{function}`")
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Location<'a> {
+    file: &'a str,
+    span: Span,
+}
+
+/// Every function and label defined across the translated files. Labels are
+/// keyed by their enclosing function, `""` for code outside of any function.
+#[derive(Default)]
+struct SymbolTable<'a> {
+    functions: HashMap<&'a str, Location<'a>>,
+    labels: HashSet<(&'a str, &'a str)>,
+    calls: HashMap<&'a str, Vec<&'a str>>,
+}
+
+/// Resolves labels and calls across all modules of a program before any
+/// assembly is written, and checks that function and label names can be
+/// written as assembler symbols that nothing else uses.
+pub fn link(program: &Program) -> Vec<LinkError> {
+    let mut errors = Vec::new();
+    let mut table = SymbolTable::default();
+    let mut error = |file: &str, span: Span, kind: LinkErrorKind| {
+        errors.push(LinkError {
+            file: String::from(file),
+            span,
+            kind,
+        })
+    };
+
+    for module in &program.modules {
+        let file = module.file.as_str();
+        for function in &module.functions {
+            let name = function.name.as_str();
+            if !is_symbol(name) {
+                error(
+                    file,
+                    function.span,
+                    LinkErrorKind::InvalidSymbol(String::from(name)),
+                );
+            } else if is_reserved(name) {
+                error(
+                    file,
+                    function.span,
+                    LinkErrorKind::ReservedSymbol(String::from(name)),
+                );
+            }
+            if table.functions.contains_key(name) {
+                error(
+                    file,
+                    function.span,
+                    LinkErrorKind::DuplicateFunction(String::from(name)),
+                );
+            } else {
+                let span = function.span;
+                table.functions.insert(name, Location { file, span });
+            }
+        }
+
+        for (function, statement) in module.statements() {
+            let function = function.unwrap_or("");
+            match &statement.command {
+                Command::Label(label) => {
+                    // labels are written after their function's name and a `$`
+                    let symbol = format!("{function}${label}");
+                    if !is_symbol(&format!("${label}")) {
+                        error(
+                            file,
+                            statement.operand(0),
+                            LinkErrorKind::InvalidSymbol(label.clone()),
+                        );
+                    } else if is_reserved(&symbol) {
+                        error(
+                            file,
+                            statement.operand(0),
+                            LinkErrorKind::ReservedSymbol(symbol),
+                        );
+                    }
+                    if !table.labels.insert((function, label)) {
+                        error(
+                            file,
+                            statement.operand(0),
+                            LinkErrorKind::DuplicateLabel {
+                                label: label.clone(),
+                                function: String::from(function),
+                            },
+                        );
+                    }
+                }
+                Command::Call(callee, _) => {
+                    table.calls.entry(function).or_default().push(callee);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    for module in &program.modules {
+        for (function, statement) in module.statements() {
+            let function = function.unwrap_or("");
+            match &statement.command {
+                Command::Goto(label) | Command::If(label)
+                    if !table.labels.contains(&(function, label.as_str())) =>
+                {
+                    error(
+                        &module.file,
+                        statement.operand(0),
+                        LinkErrorKind::UndefinedLabel {
+                            label: label.clone(),
+                            function: String::from(function),
+                        },
+                    );
+                }
+                Command::Call(callee, _) if !table.functions.contains_key(callee.as_str()) => {
+                    error(
+                        &module.file,
+                        statement.operand(0),
+                        LinkErrorKind::UndefinedFunction(callee.clone()),
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // without an entry point there is no call graph root to measure reachability from
+    if table.functions.contains_key(ENTRY_FUNCTION) {
+        let mut reachable = HashSet::from(["", ENTRY_FUNCTION]);
+        let mut pending = vec!["", ENTRY_FUNCTION];
+        while let Some(function) = pending.pop() {
+            for &callee in table.calls.get(function).into_iter().flatten() {
+                if reachable.insert(callee) {
+                    pending.push(callee);
+                }
+            }
+        }
+
+        let mut unreachable: Vec<_> = table
+            .functions
+            .iter()
+            .filter(|(name, _)| !reachable.contains(*name))
+            .collect();
+        unreachable.sort_by_key(|(_, location)| (location.file, location.span.line));
+        for (name, location) in unreachable {
+            error(
+                location.file,
+                location.span,
+                LinkErrorKind::UnreachableFunction(String::from(*name)),
+            );
+        }
+    }
+
+    errors
+}
+
+// whether `symbol` is predefined by the assembler or may be one of the labels
+// `HackAsmBackend` generates: `$$...` for the shared runtime, `CMP.n...` and
+// `END.n` for comparisons and `function$ret.n` for return addresses
+fn is_reserved(symbol: &str) -> bool {
+    let numbered = |rest: Option<&str>| {
+        let number = rest.map(|x| x.split('.').next().unwrap_or(""));
+        number.is_some_and(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit()))
+    };
+    predefined_symbols().contains_key(symbol)
+        || symbol.starts_with("$$")
+        || numbered(symbol.strip_prefix("CMP."))
+        || numbered(symbol.strip_prefix("END."))
+        || numbered(symbol.split_once("$ret.").map(|(_, x)| x))
+}
+
+/// Checks that the function the bootstrap code written for `mode` calls is
+/// defined. The error is about the whole program, with no file or span.
+pub fn check_entry(program: &Program, mode: &BootstrapMode) -> Option<LinkError> {
+    let entry = mode.entry()?;
+    if program.functions().any(|x| x.name == entry) {
+        return None;
+    }
+    Some(LinkError {
+        file: String::new(),
+        span: Span::default(),
+        kind: LinkErrorKind::UndefinedEntry(String::from(entry)),
+    })
+}
+
+/// Checks that the statics of every file get valid assembler variables under
+/// `naming` that no other file shares. Files without statics are skipped, the
+/// errors point at their first static.
+pub fn check_statics(program: &Program, naming: StaticNaming) -> Vec<LinkError> {
+    let mut errors = Vec::new();
+    let mut prefixes: HashMap<String, &str> = HashMap::new();
+    for module in &program.modules {
+        let first_static = module
+            .statements()
+            .find_map(|(_, statement)| match statement.command {
+                Command::Push(Segment::Static, _) | Command::Pop(Segment::Static, _) => {
+                    Some(statement.operand(0))
+                }
+                _ => None,
+            });
+        let Some(span) = first_static else {
+            continue;
+        };
+
+        let prefix = naming.prefix(&module.file);
+        let kind = if !is_symbol(&prefix) {
+            LinkErrorKind::InvalidStaticPrefix(prefix)
+        } else if let Some(file) = prefixes.get(&prefix) {
+            LinkErrorKind::StaticPrefixCollision {
+                prefix,
+                file: String::from(*file),
+            }
+        } else {
+            prefixes.insert(prefix, &module.file);
+            continue;
+        };
+        errors.push(LinkError {
+            file: module.file.clone(),
+            span,
+            kind,
+        });
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check_entry, check_statics, link, LinkErrorKind};
+    use crate::{
+        ir::{Module, Program},
+        parser::Parser,
+        translator::{BootstrapMode, StaticNaming},
+    };
+
+    fn parse(file: &str, source: &str) -> Module {
+        let (module, errors) = Parser::build(file, source).unwrap().parse_module();
+        assert!(errors.is_empty());
+        module
+    }
+
+    #[test]
+    fn links_valid_program() {
+        let sys = parse(
+            "Sys.vm",
+            "function Sys.init 0\ncall Main.main 0\nlabel END\ngoto END\n",
+        );
+        let main = parse(
+            "Main.vm",
+            "function Main.main 0\nlabel LOOP\nif-goto LOOP\npush constant 0\nreturn\n",
+        );
+        assert_eq!(link(&Program::new(vec![sys, main])), vec![]);
+    }
+
+    #[test]
+    fn reports_unresolved_symbols() {
+        let sys = parse(
+            "Sys.vm",
+            "function Sys.init 0\ncall Main.mian 0\ngoto LOOP\nlabel END\nlabel END\n",
+        );
+        let main = parse(
+            "Main.vm",
+            "function Main.main 0\nlabel LOOP\nreturn\nfunction Sys.init 0\n",
+        );
+        let errors: Vec<_> = link(&Program::new(vec![sys, main]))
+            .into_iter()
+            .map(|err| (err.is_warning(), err))
+            .map(|(warning, err)| (err.file, err.span.line, warning, err.kind))
+            .collect();
+
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    String::from("Sys.vm"),
+                    5,
+                    false,
+                    LinkErrorKind::DuplicateLabel {
+                        label: String::from("END"),
+                        function: String::from("Sys.init")
+                    }
+                ),
+                (
+                    String::from("Main.vm"),
+                    4,
+                    false,
+                    LinkErrorKind::DuplicateFunction(String::from("Sys.init"))
+                ),
+                (
+                    String::from("Sys.vm"),
+                    2,
+                    false,
+                    LinkErrorKind::UndefinedFunction(String::from("Main.mian"))
+                ),
+                (
+                    String::from("Sys.vm"),
+                    3,
+                    false,
+                    LinkErrorKind::UndefinedLabel {
+                        label: String::from("LOOP"),
+                        function: String::from("Sys.init")
+                    }
+                ),
+                (
+                    String::from("Main.vm"),
+                    1,
+                    true,
+                    LinkErrorKind::UnreachableFunction(String::from("Main.main"))
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_invalid_symbols() {
+        let main = parse(
+            "Main.vm",
+            "label x+y\nfunction 1bad 0\ncall 1bad 0\nlabel 2nd\n\
+             function Main.f 0\nlabel a-b\nreturn\n",
+        );
+        let errors: Vec<_> = link(&Program::new(vec![main]))
+            .into_iter()
+            .map(|err| (err.span.line, err.span.column, err.kind))
+            .collect();
+        // labels follow a `$`, so they may start with a digit
+        assert_eq!(
+            errors,
+            vec![
+                (2, 10, LinkErrorKind::InvalidSymbol(String::from("1bad"))),
+                (1, 7, LinkErrorKind::InvalidSymbol(String::from("x+y"))),
+                (6, 7, LinkErrorKind::InvalidSymbol(String::from("a-b"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_reserved_symbols() {
+        let sys = parse(
+            "Sys.vm",
+            "label $call\nlabel ok\nfunction Sys.init 0\ncall Main.f 0\nlabel ret\n\
+             function CMP.0 0\nfunction END.1.x 0\nfunction R13 0\nfunction CMP.x 0\n",
+        );
+        let main = parse(
+            "Main.vm",
+            "function Main.f 0\nlabel ret.1\nlabel ret.x\nreturn\n",
+        );
+        let errors: Vec<_> = link(&Program::new(vec![sys, main]))
+            .into_iter()
+            .filter(|err| !err.is_warning())
+            .map(|err| (err.file, err.span.line, err.kind))
+            .collect();
+        let reserved = |file: &str, line, symbol: &str| {
+            (
+                String::from(file),
+                line,
+                LinkErrorKind::ReservedSymbol(String::from(symbol)),
+            )
+        };
+        assert_eq!(
+            errors,
+            vec![
+                reserved("Sys.vm", 6, "CMP.0"),
+                reserved("Sys.vm", 7, "END.1.x"),
+                reserved("Sys.vm", 8, "R13"),
+                reserved("Sys.vm", 1, "$$call"),
+                reserved("Main.vm", 2, "Main.f$ret.1"),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_undefined_entry() {
+        let program = Program::new(vec![parse("Main.vm", "function Main.main 0\n")]);
+        assert_eq!(check_entry(&program, &BootstrapMode::None), None);
+        let err = check_entry(&program, &BootstrapMode::Standard).unwrap();
+        assert_eq!(
+            err.kind,
+            LinkErrorKind::UndefinedEntry(String::from("Sys.init"))
+        );
+        assert_eq!(
+            err.to_string(),
+            "bootstrap calls `Sys.init`, which is not defined"
+        );
+    }
+
+    #[test]
+    fn reports_unusable_static_names() {
+        let program = Program::new(vec![
+            parse("a/Main.vm", "push constant 0\n"),
+            parse("b/Main.vm", "push constant 0\npop static 0\n"),
+            parse("c/Main.vm", "function Main.f 0\npush static 1\n"),
+            parse("my-file.vm", "push static 0\n"),
+            parse("1st.vm", "push constant 0\n"),
+        ]);
+        let errors: Vec<_> = check_statics(&program, StaticNaming::Stem)
+            .into_iter()
+            .map(|err| (err.file, err.span.line, err.kind))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    String::from("c/Main.vm"),
+                    2,
+                    LinkErrorKind::StaticPrefixCollision {
+                        prefix: String::from("Main"),
+                        file: String::from("b/Main.vm")
+                    }
+                ),
+                (
+                    String::from("my-file.vm"),
+                    1,
+                    LinkErrorKind::InvalidStaticPrefix(String::from("my-file"))
+                ),
+            ]
+        );
+        assert_eq!(check_statics(&program, StaticNaming::FileName).len(), 2);
+    }
+}
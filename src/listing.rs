@@ -0,0 +1,202 @@
+//! Listings of the generated code: every VM line followed by the Hack
+//! instructions written for it, with their ROM addresses and encodings.
+
+use std::collections::HashMap;
+
+use crate::{
+    asm::Instruction,
+    assembler::{self, AssembleError},
+    ir::Program,
+    source_map::{commands, next_origin},
+};
+
+// the code written for one VM command, or for the bootstrap or the runtime
+struct Section {
+    header: String,
+    function: Option<String>,
+    lines: Vec<String>,
+    count: usize,
+}
+
+/// Lists `asm`, written by `HackAsmBackend` for `program`, under the lines of
+/// `sources`, given as `(file name, text)` pairs, it was written for. Each
+/// command's instruction count follows its line and each function ends with
+/// its total.
+pub fn listing<N, T>(
+    program: &Program,
+    sources: &[(N, T)],
+    asm: &str,
+) -> Result<String, AssembleError>
+where
+    N: AsRef<str>,
+    T: AsRef<str>,
+{
+    let sources: HashMap<&str, &str> = sources
+        .iter()
+        .map(|(name, text)| (name.as_ref(), text.as_ref()))
+        .collect();
+    let instructions: Vec<(usize, Instruction)> = asm
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let instruction = line
+                .parse()
+                .expect("Expected HackAsmBackend to emit valid assembly");
+            (i + 1, instruction)
+        })
+        .collect();
+    let mut words = assembler::assemble(&instructions)?.into_iter();
+    let mut commands = commands(program).into_iter();
+
+    let mut sections: Vec<Section> = Vec::new();
+    let mut address = 0;
+    for (_, instruction) in instructions {
+        let line = match &instruction {
+            Instruction::Comment(comment) => {
+                let origin = next_origin(&mut commands, comment);
+                let header = match &origin {
+                    Some(origin) => {
+                        let text = sources
+                            .get(origin.file.as_str())
+                            .and_then(|x| x.lines().nth(origin.line - 1))
+                            .map_or(comment.as_str(), str::trim);
+                        format!("{}:{}: {text}", origin.file, origin.line)
+                    }
+                    None => comment.clone(),
+                };
+                sections.push(Section {
+                    header,
+                    function: origin.and_then(|x| x.function),
+                    lines: Vec::new(),
+                    count: 0,
+                });
+                continue;
+            }
+            Instruction::Label(_) => format!("{:23}{instruction}", ""),
+            _ => {
+                let word = words.next().expect("Expected a word for every instruction");
+                address += 1;
+                format!("{:>5}  {word:016b}  {instruction}", address - 1)
+            }
+        };
+        let section = sections
+            .last_mut()
+            .expect("Expected HackAsmBackend to start with a comment");
+        section.lines.push(line);
+        section.count += usize::from(instruction.is_code());
+    }
+
+    let mut listing = String::new();
+    let mut total = 0;
+    for (i, section) in sections.iter().enumerate() {
+        listing.push_str(&format!(
+            "// {} ({})\n",
+            section.header,
+            count(section.count)
+        ));
+        for line in &section.lines {
+            listing.push_str(line);
+            listing.push('\n');
+        }
+        total += section.count;
+
+        let Some(function) = &section.function else {
+            continue;
+        };
+        let next = sections.get(i + 1).and_then(|x| x.function.as_ref());
+        if next != Some(function) {
+            let size: usize = sections
+                .iter()
+                .filter(|x| x.function.as_ref() == Some(function))
+                .map(|x| x.count)
+                .sum();
+            listing.push_str(&format!("// end of {function} ({})\n", count(size)));
+        }
+    }
+    listing.push_str(&format!("// total ({})\n", count(total)));
+    Ok(listing)
+}
+
+fn count(n: usize) -> String {
+    match n {
+        1 => String::from("1 instruction"),
+        n => format!("{n} instructions"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::listing;
+    use crate::translator::{parse_program, write_program, BootstrapMode, Options, StaticNaming};
+
+    #[test]
+    fn interleaves_vm_lines_and_instructions() {
+        let sources = [(
+            "Main.vm",
+            "push constant 1 // one\nfunction Main.f 0\n  label LOOP\n  goto LOOP\n",
+        )];
+        let (program, diagnostics) = parse_program(&sources, StaticNaming::Stem);
+        assert!(!diagnostics.has_errors());
+        let options = Options {
+            bootstrap: BootstrapMode::None,
+            ..Options::default()
+        };
+        let asm = write_program(&program, &options, Vec::new()).unwrap();
+        let listing = listing(&program, &sources, &String::from_utf8(asm).unwrap()).unwrap();
+        assert_eq!(
+            listing,
+            "// Main.vm:1: push constant 1 // one (7 instructions)\n\
+             \x20   0  0000000000000001  @1\n\
+             \x20   1  1110110000010000  D=A\n\
+             \x20   2  0000000000000000  @SP\n\
+             \x20   3  1111110000100000  A=M\n\
+             \x20   4  1110001100001000  M=D\n\
+             \x20   5  0000000000000000  @SP\n\
+             \x20   6  1111110111001000  M=M+1\n\
+             // Main.vm:2: function Main.f 0 (0 instructions)\n\
+             \x20                      (Main.f)\n\
+             // Main.vm:3: label LOOP (0 instructions)\n\
+             \x20                      (Main.f$LOOP)\n\
+             // Main.vm:4: goto LOOP (2 instructions)\n\
+             \x20   7  0000000000000111  @Main.f$LOOP\n\
+             \x20   8  1110101010000111  0;JMP\n\
+             // end of Main.f (2 instructions)\n\
+             // total (9 instructions)\n"
+        );
+    }
+
+    #[test]
+    fn totals_each_function() {
+        let sources = [
+            (
+                "Sys.vm",
+                "function Sys.init 0\ncall Main.f 0\nlabel END\ngoto END\n",
+            ),
+            ("Main.vm", "function Main.f 1\npush local 0\nreturn\n"),
+        ];
+        let (program, _) = parse_program(&sources, StaticNaming::Stem);
+        let options = Options {
+            shared_runtime: true,
+            optimize: true,
+            ..Options::default()
+        };
+        let asm =
+            String::from_utf8(write_program(&program, &options, Vec::new()).unwrap()).unwrap();
+        let listing = listing(&program, &sources, &asm).unwrap();
+        let lines: Vec<_> = listing.lines().filter(|x| x.starts_with("// ")).collect();
+        assert!(lines[0].starts_with("// bootstrap ("));
+        assert!(lines[1].starts_with("// runtime ("));
+        assert!(lines[2].starts_with("// Sys.vm:1: function Sys.init 0 ("));
+        assert!(lines.iter().any(|x| x.starts_with("// end of Sys.init (")));
+        assert!(lines.iter().any(|x| x.starts_with("// end of Main.f (")));
+
+        // every instruction is listed once, in ROM order
+        let words = crate::assembler::assemble_source(&asm).unwrap();
+        let addresses: Vec<usize> = listing
+            .lines()
+            .filter_map(|x| x.split_whitespace().next()?.parse().ok())
+            .collect();
+        assert_eq!(addresses, (0..words.len()).collect::<Vec<_>>());
+        assert!(listing.ends_with(&format!("// total ({} instructions)\n", words.len())));
+    }
+}
@@ -1,110 +1,642 @@
 use std::{
-    fs::{self},
-    path::PathBuf,
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
 };
 use vm_translator::{
-    code_writer::CodeWriter,
-    parser::{Command, Parser},
+    assembler::{self, AssembleError},
+    code_writer::HackAsmBackend,
+    differential::{self, DifferentialError},
+    emulator, fuzz,
+    interpreter::Interpreter,
+    ir::Program,
+    linker::{self, ENTRY_FUNCTION},
+    listing, script,
+    source_map::SourceMap,
+    symbols::SymbolTable,
+    translator::{self, Bootstrap},
+    BootstrapMode, FileOrder, Options, StaticNaming,
 };
 
+/// What the CLI writes next to the input.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Emit {
+    Asm,
+    Hack,
+    Binary,
+    Symbols,
+    Listing,
+}
+
 fn main() {
-    let mut args = std::env::args();
-    args.next();
+    let mut args = std::env::args().skip(1).peekable();
+    match args.peek().map(String::as_str) {
+        Some("run") => {
+            args.next();
+            run(args);
+            return;
+        }
+        Some("test") => {
+            args.next();
+            test(args);
+            return;
+        }
+        Some("diff") => {
+            args.next();
+            diff(args);
+            return;
+        }
+        Some("fuzz") => {
+            args.next();
+            fuzz(args);
+            return;
+        }
+        _ => {}
+    }
+
+    let mut options = Options::default();
+    let mut path = None;
+    let mut size_report = false;
+    let mut source_map = false;
+    let mut emit = Emit::Asm;
+    let mut bootstrap = None;
+    let mut order = FileOrder::default();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--emit" => {
+                emit = match args.next().as_deref() {
+                    Some("asm") => Emit::Asm,
+                    Some("hack") => Emit::Hack,
+                    Some("bin") => Emit::Binary,
+                    Some("sym") => Emit::Symbols,
+                    Some("lst") => Emit::Listing,
+                    _ => {
+                        eprintln!("ERROR: --emit expects one of asm, hack, bin, sym or lst");
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "-O" => options.optimize = true,
+            "--shared-runtime" => options.shared_runtime = true,
+            "--bootstrap" => bootstrap = Some(parse_bootstrap(args.next())),
+            "--order" => order = parse_order(args.next()),
+            "--static-naming" => options.static_naming = parse_static_naming(args.next()),
+            "--size-report" => size_report = true,
+            "--source-map" => source_map = true,
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
 
-    let path = args
-        .next()
-        .map_or_else(|| PathBuf::from("."), |arg| PathBuf::from(arg));
+    let path = path.unwrap_or_else(|| PathBuf::from("."));
     let file_stem = path.file_stem().and_then(|x| x.to_str()).unwrap();
 
+    // assembly files skip translation and go straight to the assembler
+    if path.extension().and_then(|x| x.to_str()) == Some("asm") {
+        if matches!(emit, Emit::Symbols | Emit::Listing) {
+            eprintln!(
+                "ERROR: {}: --emit sym and lst need .vm input",
+                path.display()
+            );
+            std::process::exit(1);
+        }
+        let words = assemble_file(&path);
+        let emit = if emit == Emit::Asm { Emit::Hack } else { emit };
+        write_machine_code(emit, file_stem, &words);
+        return;
+    }
+
+    let program = read_program(&path, &order, options.static_naming);
+    options.bootstrap = bootstrap_mode(bootstrap, &program);
+    for module in &program.modules {
+        let file_name = Path::new(&module.file)
+            .file_name()
+            .and_then(|x| x.to_str())
+            .expect("Expected file_name() successfully");
+        println!("Translating {file_name}...");
+    }
+
+    if size_report {
+        let inline = Options {
+            shared_runtime: false,
+            ..options.clone()
+        };
+        let shared = Options {
+            shared_runtime: true,
+            ..options.clone()
+        };
+        let (inline, shared) = (
+            translator::code_size(&program, &inline),
+            translator::code_size(&program, &shared),
+        );
+        println!("Inline runtime: {inline} instructions");
+        println!("Shared runtime: {shared} instructions");
+    }
+
+    // written next to the output, whatever it is, as ROM addresses are the same
+    if source_map {
+        let asm = translator::write_program(&program, &options, Vec::new())
+            .expect("Expected writing to memory to succeed");
+        let map = SourceMap::build(&program, &String::from_utf8_lossy(&asm));
+        let map_path = PathBuf::from(format!("./{file_stem}.map"));
+        write_atomically(&map_path, |file| file.write_all(map.to_json().as_bytes()))
+            .unwrap_or_else(|err| {
+                eprintln!("ERROR: {}: {}", map_path.display(), err);
+                std::process::exit(3);
+            });
+    }
+
+    if emit == Emit::Symbols {
+        let asm = translator::write_program(&program, &options, Vec::new())
+            .expect("Expected writing to memory to succeed");
+        let symbols = SymbolTable::build(&program, &String::from_utf8_lossy(&asm));
+        let symbols = assembled(&path, symbols);
+        let sym_path = PathBuf::from(format!("./{file_stem}.sym"));
+        write_atomically(&sym_path, |file| {
+            file.write_all(symbols.to_string().as_bytes())
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("ERROR: {}: {}", sym_path.display(), err);
+            std::process::exit(3);
+        });
+        return;
+    }
+
+    if emit == Emit::Listing {
+        let asm = translator::write_program(&program, &options, Vec::new())
+            .expect("Expected writing to memory to succeed");
+        let sources: Vec<(&str, String)> = program
+            .modules
+            .iter()
+            .map(|x| (x.file.as_str(), read_file(Path::new(&x.file))))
+            .collect();
+        let listing = listing::listing(&program, &sources, &String::from_utf8_lossy(&asm));
+        let listing = assembled(&path, listing);
+        let lst_path = PathBuf::from(format!("./{file_stem}.lst"));
+        write_atomically(&lst_path, |file| file.write_all(listing.as_bytes())).unwrap_or_else(
+            |err| {
+                eprintln!("ERROR: {}: {}", lst_path.display(), err);
+                std::process::exit(3);
+            },
+        );
+        return;
+    }
+
+    if emit != Emit::Asm {
+        let asm = translator::write_program(&program, &options, Vec::new())
+            .expect("Expected writing to memory to succeed");
+        let words = assembled(
+            &path,
+            assembler::assemble_source(&String::from_utf8_lossy(&asm)),
+        );
+        write_machine_code(emit, file_stem, &words);
+        return;
+    }
+
     let asm_path = PathBuf::from(format!("./{file_stem}.asm"));
-    let mut code_writer = CodeWriter::build(asm_path).unwrap_or_else(|err| {
-        eprintln!("ERROR: {}", err);
+    write_atomically(&asm_path, |file| {
+        translator::emit(&program, HackAsmBackend::new(file, &options)?).map(|_| ())
+    })
+    .unwrap_or_else(|err| {
+        eprintln!("ERROR: {}: {}", asm_path.display(), err);
         std::process::exit(3);
     });
+}
 
-    if path.is_dir() {
-        let iter = path
-            .read_dir()
-            .expect("Expected to read_dir() successfully")
-            .filter_map(|x| x.ok())
-            .filter(|x| {
-                x.path().is_file() && x.path().extension().and_then(|x| x.to_str()) == Some("vm")
+// `run [-O] [--shared-runtime] [--bootstrap MODE] [--order ORDER] [--static-naming NAMING] [--vm] [--symbols FILE] [--ticks N] [--ram FROM[..TO]|SYMBOL]... PATH`
+fn run(args: impl Iterator<Item = String>) {
+    let mut options = Options::default();
+    let mut bootstrap = None;
+    let mut order = FileOrder::default();
+    let mut path = None;
+    let mut ticks = 1_000_000;
+    let mut ranges = Vec::new();
+    let mut interpret = false;
+    let mut symbols = None;
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-O" => options.optimize = true,
+            "--shared-runtime" => options.shared_runtime = true,
+            "--bootstrap" => bootstrap = Some(parse_bootstrap(args.next())),
+            "--order" => order = parse_order(args.next()),
+            "--static-naming" => options.static_naming = parse_static_naming(args.next()),
+            "--vm" => interpret = true,
+            "--symbols" => {
+                let path = PathBuf::from(args.next().unwrap_or_else(|| {
+                    eprintln!("ERROR: --symbols expects a .sym file");
+                    std::process::exit(1);
+                }));
+                let table = SymbolTable::parse(&read_file(&path)).unwrap_or_else(|err| {
+                    eprintln!("ERROR: {}: {}", path.display(), err);
+                    std::process::exit(3);
+                });
+                symbols = Some(table);
+            }
+            "--ticks" => {
+                ticks = args.next().and_then(|x| x.parse().ok()).unwrap_or_else(|| {
+                    eprintln!("ERROR: --ticks expects a number");
+                    std::process::exit(1);
+                })
+            }
+            "--ram" => ranges.push(args.next().unwrap_or_default()),
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+    let path = path.unwrap_or_else(|| PathBuf::from("."));
+    let mut ranges: Vec<_> = ranges
+        .iter()
+        .map(|x| {
+            let symbol = symbols.as_ref().and_then(|symbols| symbols.get(x));
+            let range = symbol.map(|x| usize::from(x.address)..usize::from(x.address) + 1);
+            range.or_else(|| parse_range(x)).unwrap_or_else(|| {
+                eprintln!(
+                    "ERROR: --ram expects an address, a range like 256..260 or a symbol from \
+                     --symbols"
+                );
+                std::process::exit(1);
             })
-            .map(|x| x.path());
+        })
+        .collect();
+    if ranges.is_empty() {
+        ranges.push(0..16);
+    }
+
+    // executes the VM commands directly, one command per tick
+    if interpret {
+        let program = read_program(&path, &order, options.static_naming);
+        let mut vm = Interpreter::new(&program);
+        vm.bootstrap(&bootstrap_mode(bootstrap, &program));
+        let executed = vm.run(ticks);
+        let state = if vm.is_halted() { "Halted" } else { "Stopped" };
+        println!("{state} after {executed} steps");
+        print_ram(vm.ram(), ranges, None);
+        return;
+    }
 
-        for file_name in iter {
-            translate_vm_code(file_name, &mut code_writer);
+    let words = match path.extension().and_then(|x| x.to_str()) {
+        Some("asm") => assemble_file(&path),
+        Some("hack") => emulator::parse_hack(&read_file(&path)).unwrap_or_else(|err| {
+            eprintln!("ERROR: {}: {}", path.display(), err);
+            std::process::exit(3);
+        }),
+        _ => {
+            let program = read_program(&path, &order, options.static_naming);
+            options.bootstrap = bootstrap_mode(bootstrap, &program);
+            let asm = translator::write_program(&program, &options, Vec::new())
+                .expect("Expected writing to memory to succeed");
+            assembled(
+                &path,
+                assembler::assemble_source(&String::from_utf8_lossy(&asm)),
+            )
+        }
+    };
+    let mut cpu = emulator::Cpu::new(&words).unwrap_or_else(|err| {
+        eprintln!("ERROR: {}: {}", path.display(), err);
+        std::process::exit(3);
+    });
+
+    let executed = cpu.run(ticks);
+    let state = if cpu.is_halted() { "Halted" } else { "Stopped" };
+    println!("{state} after {executed} ticks");
+    let function = symbols.as_ref().and_then(|x| x.function_at(cpu.pc()));
+    match function {
+        Some(function) => println!("PC={} ({function}) A={} D={}", cpu.pc(), cpu.a(), cpu.d()),
+        None => println!("PC={} A={} D={}", cpu.pc(), cpu.a(), cpu.d()),
+    }
+    print_ram(cpu.ram(), ranges, symbols.as_ref());
+}
+
+// `test [-O] [--shared-runtime] SCRIPT.tst...`
+fn test(args: impl Iterator<Item = String>) {
+    let mut options = Options::default();
+    let mut scripts = Vec::new();
+    for arg in args {
+        match arg.as_str() {
+            "-O" => options.optimize = true,
+            "--shared-runtime" => options.shared_runtime = true,
+            _ => scripts.push(PathBuf::from(arg)),
+        }
+    }
+
+    let mut failures = 0;
+    for path in &scripts {
+        let report = script::run_script_file(path, &options).unwrap_or_else(|err| {
+            eprintln!("ERROR: {}: {}", path.display(), err);
+            std::process::exit(3);
+        });
+        if let Some(output_file) = &report.output_file {
+            let output_path = path.with_file_name(output_file);
+            write_atomically(&output_path, |file| {
+                file.write_all(report.output.as_bytes())
+            })
+            .unwrap_or_else(|err| {
+                eprintln!("ERROR: {}: {}", output_path.display(), err);
+                std::process::exit(3);
+            });
+        }
+        match &report.mismatch {
+            None => println!("PASS {}", path.display()),
+            Some(mismatch) => {
+                failures += 1;
+                println!("FAIL {}: {mismatch}", path.display());
+            }
+        }
+    }
+    if failures > 0 {
+        std::process::exit(1);
+    }
+}
+
+// `diff [--shared-runtime] [--bootstrap MODE] [--order ORDER] [--static-naming NAMING] [--steps N] PATH`
+fn diff(args: impl Iterator<Item = String>) {
+    let mut options = Options::default();
+    let mut bootstrap = None;
+    let mut order = FileOrder::default();
+    let mut path = None;
+    let mut steps = 1_000_000;
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--shared-runtime" => options.shared_runtime = true,
+            "--bootstrap" => bootstrap = Some(parse_bootstrap(args.next())),
+            "--order" => order = parse_order(args.next()),
+            "--static-naming" => options.static_naming = parse_static_naming(args.next()),
+            "--steps" => {
+                steps = args.next().and_then(|x| x.parse().ok()).unwrap_or_else(|| {
+                    eprintln!("ERROR: --steps expects a number");
+                    std::process::exit(1);
+                })
+            }
+            _ => path = Some(PathBuf::from(arg)),
+        }
+    }
+    let path = path.unwrap_or_else(|| PathBuf::from("."));
+
+    let program = read_program(&path, &order, options.static_naming);
+    options.bootstrap = bootstrap_mode(bootstrap, &program);
+    match differential::run_differential(&program, &options, steps) {
+        Ok(executed) => println!("No divergence in {executed} steps"),
+        Err(DifferentialError::Divergence(divergence)) => {
+            println!("Divergence at {divergence}");
+            std::process::exit(1);
+        }
+        Err(err) => {
+            eprintln!("ERROR: {}: {}", path.display(), err);
+            std::process::exit(3);
+        }
+    }
+}
+
+// `fuzz [--shared-runtime] [--seed N] [--cases N] [--steps N]`
+fn fuzz(args: impl Iterator<Item = String>) {
+    let mut options = Options::default();
+    let mut seed = 0;
+    let mut cases = 1000;
+    let mut steps = 20_000;
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        let mut number = |name: &str| {
+            args.next().and_then(|x| x.parse().ok()).unwrap_or_else(|| {
+                eprintln!("ERROR: {name} expects a number");
+                std::process::exit(1);
+            })
+        };
+        match arg.as_str() {
+            "--shared-runtime" => options.shared_runtime = true,
+            "--seed" => seed = number("--seed"),
+            "--cases" => cases = number("--cases"),
+            "--steps" => steps = number("--steps") as usize,
+            _ => {
+                eprintln!("ERROR: unknown argument `{arg}`");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let Some(failure) = fuzz::fuzz(seed, cases, &fuzz::Config::default(), &options, steps) else {
+        println!("No divergence in {cases} programs");
+        return;
+    };
+    println!("{failure}");
+
+    // the shrunk program, ready to be translated or run on its own
+    let dir = PathBuf::from(format!("./fuzz-{}", failure.seed));
+    for (file, text) in failure.case.sources() {
+        let path = dir.join(file);
+        fs::create_dir_all(&dir)
+            .and_then(|_| fs::write(&path, text))
+            .unwrap_or_else(|err| {
+                eprintln!("ERROR: {}: {}", path.display(), err);
+                std::process::exit(3);
+            });
+    }
+    println!("Reproducer written to {}", dir.display());
+    std::process::exit(1);
+}
+
+// `none`, `standard` or `SP,LCL,ARG,THIS,THAT[,ENTRY]`, calling `Sys.init`
+// when no entry function is given
+fn parse_bootstrap(arg: Option<String>) -> BootstrapMode {
+    let arg = arg.unwrap_or_default();
+    match arg.as_str() {
+        "none" => return BootstrapMode::None,
+        "standard" => return BootstrapMode::Standard,
+        _ => {}
+    }
+    let fields: Vec<&str> = arg.split(',').collect();
+    let values: Option<Vec<u16>> = fields
+        .iter()
+        .take(5)
+        .map(|x| x.parse().ok().filter(|&x: &u16| x < 0x8000))
+        .collect();
+    match (values, fields.len()) {
+        (Some(values), 5 | 6) if values.len() == 5 => BootstrapMode::Custom(Bootstrap {
+            sp: values[0],
+            lcl: values[1],
+            arg: values[2],
+            this: values[3],
+            that: values[4],
+            entry: String::from(fields.get(5).copied().unwrap_or(ENTRY_FUNCTION)),
+        }),
+        _ => {
+            eprintln!(
+                "ERROR: --bootstrap expects none, standard or SP,LCL,ARG,THIS,THAT[,ENTRY] \
+                 with values below 32768"
+            );
+            std::process::exit(1);
+        }
+    }
+}
+
+// `sys-first`, `name` or a comma-separated list of file names to put first
+fn parse_order(arg: Option<String>) -> FileOrder {
+    match arg.as_deref() {
+        Some("sys-first") => FileOrder::SysFirst,
+        Some("name") => FileOrder::Name,
+        Some(files) if !files.is_empty() => {
+            FileOrder::Explicit(files.split(',').map(String::from).collect())
+        }
+        _ => {
+            eprintln!("ERROR: --order expects sys-first, name or a list like Main.vm,Sys.vm");
+            std::process::exit(1);
+        }
+    }
+}
+
+// `stem` or `file-name`
+fn parse_static_naming(arg: Option<String>) -> StaticNaming {
+    match arg.as_deref() {
+        Some("stem") => StaticNaming::Stem,
+        Some("file-name") => StaticNaming::FileName,
+        _ => {
+            eprintln!("ERROR: --static-naming expects stem or file-name");
+            std::process::exit(1);
         }
-    } else {
-        translate_vm_code(path, &mut code_writer);
     }
 }
 
-fn translate_vm_code(file_name: PathBuf, code_writer: &mut CodeWriter) {
-    let file_path = file_name.to_str().expect("Expected to_str() successfully");
-    let file_name = file_name
-        .file_name()
-        .and_then(|x| x.to_str())
-        .expect("Expected file_name() successfully");
+// the requested bootstrap, or the one detected from the presence of `Sys.vm`
+fn bootstrap_mode(requested: Option<BootstrapMode>, program: &Program) -> BootstrapMode {
+    let mode = requested.unwrap_or_else(|| BootstrapMode::detect(program));
+    if let Some(err) = linker::check_entry(program, &mode) {
+        eprintln!("ERROR: {err}");
+        std::process::exit(3);
+    }
+    mode
+}
 
-    code_writer.set_file_name(String::from(file_name));
+fn print_ram(ram: &[i16], ranges: Vec<std::ops::Range<usize>>, symbols: Option<&SymbolTable>) {
+    for address in ranges.into_iter().flatten() {
+        let Some(value) = ram.get(address) else {
+            continue;
+        };
+        let name = symbols.and_then(|x| x.static_at(address as u16));
+        match name {
+            Some(name) => println!("RAM[{address}] = {value} ({name})"),
+            None => println!("RAM[{address}] = {value}"),
+        }
+    }
+}
 
-    println!("Translating {file_name}...");
+// `256` or `256..260`
+fn parse_range(range: &str) -> Option<std::ops::Range<usize>> {
+    match range.split_once("..") {
+        Some((from, to)) => Some(from.parse().ok()?..to.parse().ok()?),
+        None => {
+            let address = range.parse().ok()?;
+            Some(address..address + 1)
+        }
+    }
+}
 
-    let file = fs::read_to_string(&file_path).unwrap_or_else(|err| {
-        eprintln!("ERROR: {}: {}", file_path, err);
+fn read_file(path: &Path) -> String {
+    fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("ERROR: {}: {}", path.display(), err);
         std::process::exit(2);
-    });
+    })
+}
 
-    let parser = Parser::build(&file).unwrap_or_else(|err| {
-        eprintln!("ERROR: {}: {}", file_path, err);
+fn assemble_file(path: &Path) -> Vec<u16> {
+    assembler::assemble_source(&read_file(path)).unwrap_or_else(|err| {
+        eprintln!("ERROR: {}: {}", path.display(), err);
         std::process::exit(3);
-    });
+    })
+}
 
-    let mut current_function_name: Option<&str> = None;
+// the result of assembling the code translated from `path`, which the
+// translator does not guarantee to assemble, exiting with the error if not
+fn assembled<T>(path: &Path, result: Result<T, AssembleError>) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("ERROR: {}: generated assembly: {}", path.display(), err);
+        std::process::exit(3);
+    })
+}
 
-    fn build_full_label(label: &str, current_function_name: Option<&str>) -> String {
-        let mut full_label = String::new();
-        if let Some(function_name) = current_function_name {
-            full_label.push_str(&function_name);
+// reads the `.vm` file at `path`, or every `.vm` file in it in `order` when
+// it is a directory, exiting with the diagnostics if any of them is invalid
+fn read_program(path: &Path, order: &FileOrder, naming: StaticNaming) -> Program {
+    let files: Vec<PathBuf> = if path.is_dir() {
+        let mut files: Vec<PathBuf> = path
+            .read_dir()
+            .expect("Expected to read_dir() successfully")
+            .filter_map(|x| x.ok())
+            .filter(|x| {
+                x.path().is_file() && x.path().extension().and_then(|x| x.to_str()) == Some("vm")
+            })
+            .map(|x| x.path())
+            .collect();
+        if files.is_empty() {
+            eprintln!("ERROR: no .vm files in {}", path.display());
+            std::process::exit(2);
         }
-        full_label.push('$');
-        full_label.push_str(label);
-        full_label
+        order.sort(&mut files).unwrap_or_else(|missing| {
+            eprintln!("ERROR: {}: no file named {missing}", path.display());
+            std::process::exit(2);
+        });
+        files
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let sources: Vec<(String, String)> = files
+        .into_iter()
+        .map(|file_name| {
+            let file_path = file_name.to_str().expect("Expected to_str() successfully");
+            let file = fs::read_to_string(file_path).unwrap_or_else(|err| {
+                eprintln!("ERROR: {}: {}", file_path, err);
+                std::process::exit(2);
+            });
+            (String::from(file_path), file)
+        })
+        .collect();
+
+    // parse every file up front so all errors are reported before any output is written
+    let (program, diagnostics) = translator::parse_program(&sources, naming);
+    if !diagnostics.is_empty() {
+        eprint!("{diagnostics}");
+    }
+    if diagnostics.has_errors() {
+        std::process::exit(3);
     }
 
-    for command in parser {
-        println!("{command}");
-        code_writer.write_comment(&command);
-        match command {
-            Command::ArithmeticLogical(_) => {
-                code_writer.write_arithmetic(command);
-            }
-            Command::Push(_, _) | Command::Pop(_, _) => {
-                code_writer.write_push_pop(command);
-            }
-            Command::Label(label) => {
-                let full_label = build_full_label(label, current_function_name);
-                code_writer.write_label(&full_label);
-            }
-            Command::Goto(label) => {
-                let full_label = build_full_label(label, current_function_name);
-                code_writer.write_goto(&full_label);
-            }
-            Command::If(label) => {
-                let full_label = build_full_label(label, current_function_name);
-                code_writer.write_if(&full_label);
-            }
-            Command::Function(function_name, n_vars) => {
-                current_function_name = Some(function_name);
-                code_writer.write_function(function_name, n_vars);
-            }
-            Command::Return => {
-                code_writer.write_return();
-            }
-            Command::Call(function_name, n_args) => {
-                code_writer.write_call(function_name, n_args);
-            }
+    program
+}
+
+// writes `.hack` text or raw big-endian words to `./{file_stem}.hack`/`.bin`
+fn write_machine_code(emit: Emit, file_stem: &str, words: &[u16]) {
+    let (path, bytes) = match emit {
+        Emit::Binary => (format!("./{file_stem}.bin"), assembler::to_binary(words)),
+        _ => (
+            format!("./{file_stem}.hack"),
+            assembler::to_hack(words).into_bytes(),
+        ),
+    };
+    let path = PathBuf::from(path);
+    write_atomically(&path, |file| file.write_all(&bytes)).unwrap_or_else(|err| {
+        eprintln!("ERROR: {}: {}", path.display(), err);
+        std::process::exit(3);
+    });
+}
+
+// writes to a temporary file next to `path` and renames it into place, so a
+// failed write never leaves a truncated file behind
+fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut File) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let result = File::create(&tmp_path).and_then(|mut file| {
+        write(&mut file)?;
+        file.sync_all()
+    });
+    match result {
+        Ok(()) => fs::rename(&tmp_path, path),
+        Err(err) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(err)
         }
     }
 }
@@ -0,0 +1,277 @@
+//! Peephole optimizations over the Hack assembly emitted by `HackAsmBackend`.
+//!
+//! Every pass only looks at straight-line code: labels and jumps end the
+//! window, and comments are skipped over. The passes run until none of them
+//! finds anything left to remove.
+
+use crate::asm::Instruction;
+
+pub fn optimize(mut code: Vec<Instruction>) -> Vec<Instruction> {
+    let passes: [fn(&mut Vec<Instruction>) -> bool; 6] = [
+        remove_redundant_loads,
+        remove_cancelling_pairs,
+        remove_push_pop_round_trips,
+        remove_dead_loads,
+        store_constants_directly,
+        remove_dead_temp_stores,
+    ];
+    loop {
+        let mut changed = false;
+        for pass in passes {
+            changed |= pass(&mut code);
+        }
+        if !changed {
+            return code;
+        }
+    }
+}
+
+fn is_a(instruction: &Instruction, value: &str) -> bool {
+    matches!(instruction, Instruction::A(x) if x == value)
+}
+
+fn is_c(instruction: &Instruction, line: &str) -> bool {
+    matches!(instruction, Instruction::C { .. }) && instruction.to_string() == line
+}
+
+// indices of every non-comment instruction
+fn code_indices(code: &[Instruction]) -> Vec<usize> {
+    (0..code.len())
+        .filter(|&i| !matches!(code[i], Instruction::Comment(_)))
+        .collect()
+}
+
+fn remove(code: &mut Vec<Instruction>, removed: &[bool]) -> bool {
+    let mut i = 0;
+    code.retain(|_| {
+        i += 1;
+        !removed[i - 1]
+    });
+    removed.contains(&true)
+}
+
+// whether `register` is overwritten before being read, starting at `from`
+fn is_dead(code: &[Instruction], from: usize, register: char) -> bool {
+    for instruction in &code[from..] {
+        match instruction {
+            Instruction::Comment(_) => {}
+            Instruction::Label(_) => return false,
+            Instruction::A(_) if register == 'A' => return true,
+            Instruction::A(_) => {}
+            c => {
+                if c.reads(register) || c.jumps() {
+                    return false;
+                }
+                if c.writes(register) {
+                    return true;
+                }
+            }
+        }
+    }
+    true
+}
+
+// `@X` while A already holds X, e.g. the `@SP` reload in `@SP M=M+1 @SP M=M-1`
+fn remove_redundant_loads(code: &mut Vec<Instruction>) -> bool {
+    let mut removed = vec![false; code.len()];
+    let mut known: Option<&str> = None;
+    for (i, instruction) in code.iter().enumerate() {
+        match instruction {
+            Instruction::Comment(_) => {}
+            Instruction::Label(_) => known = None,
+            Instruction::A(value) if known == Some(value) => removed[i] = true,
+            Instruction::A(value) => known = Some(value),
+            c if c.writes('A') || c.jumps() => known = None,
+            _ => {}
+        }
+    }
+    remove(code, &removed)
+}
+
+// `M=M+1 M=M-1`, and copies straight back into the register they came from
+fn remove_cancelling_pairs(code: &mut Vec<Instruction>) -> bool {
+    let mut removed = vec![false; code.len()];
+    let indices = code_indices(code);
+    let mut k = 0;
+    while k + 1 < indices.len() {
+        let (i, j) = (indices[k], indices[k + 1]);
+        let (first, second) = (&code[i], &code[j]);
+        if (is_c(first, "M=M+1") && is_c(second, "M=M-1"))
+            || (is_c(first, "M=M-1") && is_c(second, "M=M+1"))
+        {
+            removed[i] = true;
+            removed[j] = true;
+            k += 2;
+        } else if (is_c(first, "M=D") && is_c(second, "D=M"))
+            || (is_c(first, "D=M") && is_c(second, "M=D"))
+        {
+            removed[j] = true;
+            k += 2;
+        } else {
+            k += 1;
+        }
+    }
+    remove(code, &removed)
+}
+
+// `@SP A=M M=D @SP A=M D=M`: a value pushed then immediately popped is
+// still in D, only the store to the stack needs to stay
+fn remove_push_pop_round_trips(code: &mut Vec<Instruction>) -> bool {
+    let mut removed = vec![false; code.len()];
+    let indices = code_indices(code);
+    let pattern = ["@SP", "A=M", "M=D", "@SP", "A=M", "D=M"];
+    let mut k = 0;
+    while k + pattern.len() <= indices.len() {
+        let window = &indices[k..k + pattern.len()];
+        let matches = window
+            .iter()
+            .zip(pattern)
+            .all(|(&i, line)| code[i].to_string() == line);
+        if matches {
+            for &i in &window[3..] {
+                removed[i] = true;
+            }
+            k += pattern.len();
+        } else {
+            k += 1;
+        }
+    }
+    remove(code, &removed)
+}
+
+// `@X` immediately followed by another A-instruction
+fn remove_dead_loads(code: &mut Vec<Instruction>) -> bool {
+    let mut removed = vec![false; code.len()];
+    let indices = code_indices(code);
+    for pair in indices.windows(2) {
+        if matches!(code[pair[0]], Instruction::A(_)) && matches!(code[pair[1]], Instruction::A(_))
+        {
+            removed[pair[0]] = true;
+        }
+    }
+    remove(code, &removed)
+}
+
+// `D=0 @SP A=M M=D` becomes `@SP A=M M=0` when nothing reads D afterwards
+fn store_constants_directly(code: &mut Vec<Instruction>) -> bool {
+    let mut removed = vec![false; code.len()];
+    let indices = code_indices(code);
+    let mut k = 0;
+    while k + 1 < indices.len() {
+        let constant = ["0", "1", "-1"]
+            .into_iter()
+            .find(|c| is_c(&code[indices[k]], &format!("D={c}")));
+        let Some(constant) = constant else {
+            k += 1;
+            continue;
+        };
+
+        // the address may be loaded and dereferenced first, as in `@SP A=M`
+        let mut store = k + 1;
+        if matches!(code[indices[store]], Instruction::A(_)) {
+            store += 1;
+        }
+        if store < indices.len() && is_c(&code[indices[store]], "A=M") {
+            store += 1;
+        }
+        if store < indices.len()
+            && is_c(&code[indices[store]], "M=D")
+            && is_dead(code, indices[store] + 1, 'D')
+        {
+            removed[indices[k]] = true;
+            code[indices[store]] = Instruction::c(Some("M"), constant, None);
+            k = store + 1;
+        } else {
+            k += 1;
+        }
+    }
+    remove(code, &removed)
+}
+
+// `@R13 M=...` overwritten before R13 is read again. An address computed into
+// A may be R13, as `pop pointer` can point `this` and `that` anywhere, so
+// reading memory through it keeps the store.
+fn remove_dead_temp_stores(code: &mut Vec<Instruction>) -> bool {
+    let mut removed = vec![false; code.len()];
+    let indices = code_indices(code);
+    for (k, pair) in indices.windows(2).enumerate() {
+        let is_store = matches!(
+            &code[pair[1]],
+            Instruction::C { dest: Some(dest), jump: None, .. } if dest == "M"
+        );
+        if !is_a(&code[pair[0]], "R13") || !is_store {
+            continue;
+        }
+
+        // whether A is R13 for sure, and whether it may be
+        let (mut is_r13, mut may_be_r13) = (true, true);
+        for &i in &indices[k + 2..] {
+            match &code[i] {
+                Instruction::Label(_) => break,
+                Instruction::A(value) => {
+                    is_r13 = value == "R13" || value == "13";
+                    may_be_r13 = is_r13;
+                }
+                c if c.jumps() || (may_be_r13 && c.reads('M')) => break,
+                c if is_r13 && c.writes('M') => {
+                    removed[pair[1]] = true;
+                    break;
+                }
+                c if c.writes('A') => (is_r13, may_be_r13) = (false, true),
+                _ => {}
+            }
+        }
+    }
+    remove(code, &removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::optimize;
+    use crate::asm::Instruction;
+
+    fn parse(code: &str) -> Vec<Instruction> {
+        code.split_whitespace()
+            .map(|x| x.parse().unwrap())
+            .collect()
+    }
+
+    fn optimized(code: &str) -> String {
+        let code: Vec<String> = optimize(parse(code))
+            .iter()
+            .map(|x| x.to_string())
+            .collect();
+        code.join(" ")
+    }
+
+    #[test]
+    fn removes_push_pop_round_trip() {
+        let push_pop = "@7 D=A @SP A=M M=D @SP M=M+1 @SP M=M-1 A=M D=M @R13 M=D";
+        assert_eq!(optimized(push_pop), "@7 D=A @SP A=M M=D @R13 M=D");
+    }
+
+    #[test]
+    fn stores_constants_directly() {
+        let locals = "D=0 @SP A=M M=D @SP M=M+1 D=0 @SP A=M M=D @SP M=M+1 @0 D=A";
+        assert_eq!(
+            optimized(locals),
+            "@SP A=M M=0 @SP M=M+1 A=M M=0 @SP M=M+1 @0 D=A"
+        );
+    }
+
+    #[test]
+    fn keeps_stores_read_after_a_jump() {
+        let code = "@R13 M=D @LOOP 0;JMP (LOOP) @R13 D=M";
+        assert_eq!(optimized(code), code);
+        assert_eq!(optimized("@R13 M=D @5 D=A @R13 M=D"), "@5 D=A @R13 M=D");
+    }
+
+    #[test]
+    fn keeps_stores_read_through_pointers() {
+        // `this 0` is R13 once `pop pointer 0` set THIS to 13
+        let code = "@R13 M=D @THIS A=M D=M @R13 M=D";
+        assert_eq!(optimized(code), code);
+        let code = "@R13 M=D @THIS A=M M=D @R13 M=D";
+        assert_eq!(optimized(code), "@THIS A=M M=D @R13 M=D");
+    }
+}
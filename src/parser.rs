@@ -1,35 +1,149 @@
+use crate::ir::{Function, Module};
 use std::{
     error::Error,
     fmt::{self, Display},
+    iter::Enumerate,
     str::Lines,
 };
 
 #[derive(Clone)]
 pub struct Parser<'a> {
-    file: Lines<'a>,
+    file_name: String,
+    file: Enumerate<Lines<'a>>,
 }
 
 impl<'a> Parser<'a> {
-    pub fn build(file_contents: &str) -> Result<Parser, Box<dyn Error>> {
-        let file = file_contents.lines();
-        Ok(Parser { file })
+    pub fn build(file_name: &str, file_contents: &'a str) -> Result<Parser<'a>, Box<dyn Error>> {
+        let file = file_contents.lines().enumerate();
+        Ok(Parser {
+            file_name: String::from(file_name),
+            file,
+        })
     }
+
+    /// Parses the whole file into an owned [`Module`], grouping commands under
+    /// the function that declares them. Statements that fail to parse are
+    /// skipped and returned alongside the module.
+    pub fn parse_module(self) -> (Module, Vec<ParseError>) {
+        let mut module = Module::new(&self.file_name);
+        let mut errors = Vec::new();
+        for statement in self {
+            match statement {
+                Ok(Statement {
+                    command: Command::Function(name, n_vars),
+                    operands,
+                    ..
+                }) => {
+                    let mut function = Function::new(&name, n_vars);
+                    function.span = operands[0];
+                    module.functions.push(function);
+                }
+                Ok(statement) => match module.functions.last_mut() {
+                    Some(function) => function.body.push(statement),
+                    None => module.top_level.push(statement),
+                },
+                Err(err) => errors.push(err),
+            }
+        }
+        (module, errors)
+    }
+}
+
+/// Location of a token in a source file. `line` and `column` are 1-based,
+/// `len` is measured in characters.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    pub len: usize,
 }
 
-#[derive(Debug)]
-pub enum Command<'a> {
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub file: String,
+    pub span: Span,
+    pub kind: ParseErrorKind,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    UnknownCommand(String),
+    BadSegment(String),
+    MissingOperand(&'static str),
+    NonNumericIndex {
+        operand: &'static str,
+        found: String,
+    },
+    TrailingTokens(String),
+}
+
+impl Display for ParseErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseErrorKind::UnknownCommand(command) => write!(f, "unknown command `{command}`"),
+            ParseErrorKind::BadSegment(segment) => write!(f, "invalid segment `{segment}`"),
+            ParseErrorKind::MissingOperand(operand) => write!(f, "expected {operand}"),
+            ParseErrorKind::NonNumericIndex { operand, found } => {
+                write!(f, "expected numeric {operand}, found `{found}`")
+            }
+            ParseErrorKind::TrailingTokens(tokens) => write!(f, "unexpected tokens `{tokens}`"),
+        }
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.span.line, self.span.column, self.kind
+        )
+    }
+}
+
+impl Error for ParseError {}
+
+/// A parsed command along with the spans of its tokens, so later passes can
+/// point at the offending operand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Statement {
+    pub command: Command,
+    pub span: Span,
+    pub operands: Vec<Span>,
+}
+
+impl Statement {
+    /// Span of the operand at `index`, falling back to the whole statement for
+    /// statements that were built programmatically rather than parsed.
+    pub fn operand(&self, index: usize) -> Span {
+        self.operands.get(index).copied().unwrap_or(self.span)
+    }
+}
+
+impl From<Command> for Statement {
+    fn from(command: Command) -> Self {
+        Statement {
+            command,
+            span: Span::default(),
+            operands: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
     ArithmeticLogical(ArithmeticLogical),
-    Push(&'a str, usize),
-    Pop(&'a str, usize),
-    Label(&'a str),
-    Goto(&'a str),
-    If(&'a str),
-    Function(&'a str, usize),
+    Push(Segment, usize),
+    Pop(Segment, usize),
+    Label(String),
+    Goto(String),
+    If(String),
+    Function(String, usize),
     Return,
-    Call(&'a str, usize),
+    Call(String, usize),
 }
 
-impl<'a> Display for Command<'a> {
+impl Display for Command {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Command::ArithmeticLogical(al) => write!(f, "{al}"),
@@ -47,7 +161,51 @@ impl<'a> Display for Command<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment {
+    Argument,
+    Local,
+    Static,
+    Constant,
+    This,
+    That,
+    Pointer,
+    Temp,
+}
+
+impl Segment {
+    pub fn parse(segment: &str) -> Option<Segment> {
+        match segment {
+            "argument" => Some(Segment::Argument),
+            "local" => Some(Segment::Local),
+            "static" => Some(Segment::Static),
+            "constant" => Some(Segment::Constant),
+            "this" => Some(Segment::This),
+            "that" => Some(Segment::That),
+            "pointer" => Some(Segment::Pointer),
+            "temp" => Some(Segment::Temp),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Segment::Argument => "argument",
+            Segment::Local => "local",
+            Segment::Static => "static",
+            Segment::Constant => "constant",
+            Segment::This => "this",
+            Segment::That => "that",
+            Segment::Pointer => "pointer",
+            Segment::Temp => "temp",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ArithmeticLogical {
     Add,
     Sub,
@@ -78,63 +236,176 @@ impl Display for ArithmeticLogical {
 }
 
 impl<'a> Iterator for Parser<'a> {
-    type Item = Command<'a>;
+    type Item = Result<Statement, ParseError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let line = loop {
-            match self.file.next() {
-                None => return None,
-                Some(line) => {
-                    let line = line.trim();
-                    let first_char = line.chars().nth(0).unwrap_or(' ');
-                    if !line.is_empty() && first_char != '/' {
-                        break Some(line);
-                    }
-                }
+        for (index, line) in self.file.by_ref() {
+            let line = strip_comment(line);
+            if !line.trim().is_empty() {
+                return Some(parse_command(&self.file_name, index + 1, line));
             }
-        };
-        let line = line.expect("Should have value");
-        let command = parse_command(line).unwrap();
-        Some(command)
+        }
+        None
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find("//") {
+        Some(start) => &line[..start],
+        None => line,
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Token<'a> {
+    text: &'a str,
+    offset: usize, // byte offset of the token within its line
+    span: Span,
+}
+
+// splits a line into whitespace separated tokens, keeping track of their spans
+fn tokenize(line_number: usize, line: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut start: Option<(usize, usize)> = None; // (byte offset, column)
+    let mut column = 0;
+    for (offset, c) in line.char_indices() {
+        column += 1;
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some((offset, column)),
+            (true, Some((token_start, token_column))) => {
+                tokens.push(Token {
+                    text: &line[token_start..offset],
+                    offset: token_start,
+                    span: Span {
+                        line: line_number,
+                        column: token_column,
+                        len: column - token_column,
+                    },
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some((token_start, token_column)) = start {
+        tokens.push(Token {
+            text: &line[token_start..],
+            offset: token_start,
+            span: Span {
+                line: line_number,
+                column: token_column,
+                len: column + 1 - token_column,
+            },
+        });
     }
+    tokens
+}
+
+struct Cursor<'f, 'a> {
+    file: &'f str,
+    line: &'a str,
+    tokens: Vec<Token<'a>>,
+    position: usize,
+    end: Span, // points just past the last token, used when an operand is missing
 }
 
-fn parse_command<'a>(line: &'a str) -> Result<Command<'a>, String> {
-    if line.trim().is_empty() || line.starts_with('/') {
-        return Err(format!("Error: Invalid line: {line}"));
+impl<'f, 'a> Cursor<'f, 'a> {
+    fn new(file: &'f str, line_number: usize, line: &'a str) -> Cursor<'f, 'a> {
+        let tokens = tokenize(line_number, line);
+        let end = tokens.last().map_or(
+            Span {
+                line: line_number,
+                column: 1,
+                len: 1,
+            },
+            |last| Span {
+                line: line_number,
+                column: last.span.column + last.span.len,
+                len: 1,
+            },
+        );
+        Cursor {
+            file,
+            line,
+            tokens,
+            position: 0,
+            end,
+        }
     }
-    let line = line.trim();
-    // todo: explore one pass to optimize from O(2n) to O(n)
-    let mut tokens = line.split_whitespace();
 
-    let command = tokens
-        .next()
-        .ok_or_else(|| format!("Error: Invalid command: {line}"))?;
+    fn error(&self, span: Span, kind: ParseErrorKind) -> ParseError {
+        ParseError {
+            file: String::from(self.file),
+            span,
+            kind,
+        }
+    }
 
-    let command = match command {
+    fn expect(&mut self, operand: &'static str) -> Result<Token<'a>, ParseError> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .copied()
+            .ok_or_else(|| self.error(self.end, ParseErrorKind::MissingOperand(operand)))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect_number(&mut self, operand: &'static str) -> Result<usize, ParseError> {
+        let token = self.expect(operand)?;
+        token.text.parse::<usize>().map_err(|_| {
+            self.error(
+                token.span,
+                ParseErrorKind::NonNumericIndex {
+                    operand,
+                    found: String::from(token.text),
+                },
+            )
+        })
+    }
+
+    fn finish(self, command: Command) -> Result<Statement, ParseError> {
+        if let (Some(first), Some(last)) = (self.tokens.get(self.position), self.tokens.last()) {
+            let trailing = &self.line[first.offset..last.offset + last.text.len()];
+            let span = Span {
+                len: trailing.chars().count(),
+                ..first.span
+            };
+            return Err(self.error(span, ParseErrorKind::TrailingTokens(String::from(trailing))));
+        }
+
+        let first = self.tokens[0].span;
+        let last = self.tokens[self.position - 1].span;
+        Ok(Statement {
+            command,
+            span: Span {
+                len: last.column + last.len - first.column,
+                ..first
+            },
+            operands: self.tokens[1..self.position]
+                .iter()
+                .map(|x| x.span)
+                .collect(),
+        })
+    }
+}
+
+fn parse_command(file: &str, line_number: usize, line: &str) -> Result<Statement, ParseError> {
+    let mut tokens = Cursor::new(file, line_number, line);
+
+    let command = tokens.expect("command")?;
+
+    let command = match command.text {
         "push" => {
-            let segment = tokens
-                .next()
-                .ok_or_else(|| format!("Error: Expected segment for: {line}"))?;
-            let segment = validate_segment(segment)?;
-            let index = tokens
-                .next()
-                .ok_or_else(|| format!("Error: Expected index for: {line}"))?
-                .parse::<usize>()
-                .map_err(|_| format!("Error: Expected numeric index for: {line}"))?;
+            let segment = tokens.expect("segment")?;
+            let segment = validate_segment(&tokens, segment)?;
+            let index = tokens.expect_number("index")?;
             Command::Push(segment, index)
         }
         "pop" => {
-            let segment = tokens
-                .next()
-                .ok_or_else(|| format!("Error: Expected segment for: {line}"))?;
-            let segment = validate_segment(segment)?;
-            let index = tokens
-                .next()
-                .ok_or_else(|| format!("Error: Expected index for: {line}"))?
-                .parse::<usize>()
-                .map_err(|_| format!("Error: Expected numeric index for: {line}"))?;
-
+            let segment = tokens.expect("segment")?;
+            let segment = validate_segment(&tokens, segment)?;
+            let index = tokens.expect_number("index")?;
             Command::Pop(segment, index)
         }
         "add" => Command::ArithmeticLogical(ArithmeticLogical::Add),
@@ -146,76 +417,47 @@ fn parse_command<'a>(line: &'a str) -> Result<Command<'a>, String> {
         "and" => Command::ArithmeticLogical(ArithmeticLogical::And),
         "or" => Command::ArithmeticLogical(ArithmeticLogical::Or),
         "not" => Command::ArithmeticLogical(ArithmeticLogical::Not),
-        "label" => {
-            let label = tokens
-                .next()
-                .ok_or_else(|| format!("Error: Expected label for: {line}"))?;
-
-            Command::Label(label)
-        }
-        "goto" => {
-            let label = tokens
-                .next()
-                .ok_or_else(|| format!("Error: Expected label for: {line}"))?;
-
-            Command::Goto(label)
-        }
-        "if-goto" => {
-            let label = tokens
-                .next()
-                .ok_or_else(|| format!("Error: Expected label for: {line}"))?;
-
-            Command::If(label)
-        }
+        "label" => Command::Label(String::from(tokens.expect("label")?.text)),
+        "goto" => Command::Goto(String::from(tokens.expect("label")?.text)),
+        "if-goto" => Command::If(String::from(tokens.expect("label")?.text)),
         "function" => {
-            let function_name = tokens
-                .next()
-                .ok_or_else(|| format!("Error: Expected function_name for: {line}"))?;
-
-            let n_vars = tokens
-                .next()
-                .ok_or_else(|| format!("Error: Expected n_vars for: {line}"))?
-                .parse::<usize>()
-                .map_err(|_| format!("Error: Expected numeric n_vars for: {line}"))?;
-
+            let function_name = String::from(tokens.expect("function name")?.text);
+            let n_vars = tokens.expect_number("n_vars")?;
             Command::Function(function_name, n_vars)
         }
         "call" => {
-            let function_name = tokens
-                .next()
-                .ok_or_else(|| format!("Error: Expected function_name for: {line}"))?;
-
-            let n_args = tokens
-                .next()
-                .ok_or_else(|| format!("Error: Expected n_args for: {line}"))?
-                .parse::<usize>()
-                .map_err(|_| format!("Error: Expected numeric n_args for: {line}"))?;
-
+            let function_name = String::from(tokens.expect("function name")?.text);
+            let n_args = tokens.expect_number("n_args")?;
             Command::Call(function_name, n_args)
         }
         "return" => Command::Return,
-        _ => return Err(format!("Error: Invalid command: {line}")),
+        _ => {
+            return Err(tokens.error(
+                command.span,
+                ParseErrorKind::UnknownCommand(String::from(command.text)),
+            ))
+        }
     };
-    Ok(command)
+    tokens.finish(command)
 }
 
-fn validate_segment<'a>(segment: &'a str) -> Result<&'a str, String> {
-    match segment {
-        "argument" | "constant" | "local" | "static" | "this" | "that" | "pointer" | "temp" => {
-            Ok(segment)
-        }
-        _ => Err(format!("Error: Invalid segment: {segment}")),
-    }
+fn validate_segment(tokens: &Cursor, segment: Token) -> Result<Segment, ParseError> {
+    Segment::parse(segment.text).ok_or_else(|| {
+        tokens.error(
+            segment.span,
+            ParseErrorKind::BadSegment(String::from(segment.text)),
+        )
+    })
 }
 #[cfg(test)]
 mod tests {
-    use super::{parse_command, Command};
+    use super::{parse_command, Command, ParseErrorKind, Parser, Segment, Span};
 
     #[test]
     fn parse_push_command() {
-        match parse_command("push local 3").unwrap() {
+        match parse_command("Test.vm", 1, "push local 3").unwrap().command {
             Command::Push(segment, index) => {
-                assert_eq!(segment, "local");
+                assert_eq!(segment, Segment::Local);
                 assert_eq!(index, 3);
             }
             _ => panic!(),
@@ -224,12 +466,87 @@ mod tests {
 
     #[test]
     fn parse_pop_command() {
-        match parse_command("pop this 4").unwrap() {
-            Command::Push(segment, index) => {
-                assert_eq!(segment, "this");
+        match parse_command("Test.vm", 1, "pop this 4").unwrap().command {
+            Command::Pop(segment, index) => {
+                assert_eq!(segment, Segment::This);
                 assert_eq!(index, 4);
             }
             _ => panic!(),
         }
     }
+
+    #[test]
+    fn statements_carry_operand_spans() {
+        let statement = parse_command("Test.vm", 2, "\tcall Foo.bar  2 ").unwrap();
+        assert_eq!(
+            statement.span,
+            Span {
+                line: 2,
+                column: 2,
+                len: 15
+            }
+        );
+        assert_eq!(
+            statement.operands,
+            vec![
+                Span {
+                    line: 2,
+                    column: 7,
+                    len: 7
+                },
+                Span {
+                    line: 2,
+                    column: 16,
+                    len: 1
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_errors_carry_spans() {
+        let err = parse_command("Test.vm", 7, "  push locl 3").unwrap_err();
+        assert_eq!(err.file, "Test.vm");
+        assert_eq!(err.kind, ParseErrorKind::BadSegment(String::from("locl")));
+        assert_eq!(
+            err.span,
+            Span {
+                line: 7,
+                column: 8,
+                len: 4
+            }
+        );
+
+        let err = parse_command("Test.vm", 1, "push local").unwrap_err();
+        assert_eq!(err.kind, ParseErrorKind::MissingOperand("index"));
+        assert_eq!(err.span.column, 11);
+
+        let err = parse_command("Test.vm", 1, "pop temp x").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::NonNumericIndex {
+                operand: "index",
+                found: String::from("x")
+            }
+        );
+
+        let err = parse_command("Test.vm", 1, "add 1  2").unwrap_err();
+        assert_eq!(
+            err.kind,
+            ParseErrorKind::TrailingTokens(String::from("1  2"))
+        );
+        assert_eq!(err.span.column, 5);
+        assert_eq!(err.span.len, 4);
+    }
+
+    #[test]
+    fn parser_reports_every_error() {
+        let source = "// comment\npush constant 1 // inline\nfoo\n\npop banana 2\nadd\n";
+        let results: Vec<_> = Parser::build("Test.vm", source).unwrap().collect();
+        assert_eq!(results.len(), 4);
+        assert!(results[0].is_ok());
+        assert_eq!(results[1].as_ref().unwrap_err().span.line, 3);
+        assert_eq!(results[2].as_ref().unwrap_err().span.line, 5);
+        assert!(results[3].is_ok());
+    }
 }
@@ -0,0 +1,653 @@
+//! Runs the nand2tetris `.tst` test scripts shipped with every sample and
+//! compares their output with the expected `.cmp` table.
+//!
+//! `load X.asm` translates every `.vm` file next to the script and runs the
+//! result on the CPU emulator, `load X.hack` runs an assembled program, and
+//! `load` or `load X.vm` runs the VM code on the interpreter.
+
+use std::{
+    error::Error,
+    fmt::{self, Display},
+    fs,
+    path::Path,
+};
+
+use crate::{
+    assembler,
+    emulator::{self, Cpu},
+    interpreter::Interpreter,
+    linker::ENTRY_FUNCTION,
+    translator::{self, BootstrapMode, FileOrder, Options},
+};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ScriptError {
+    /// 1-based line of the script command that failed.
+    pub line: usize,
+    pub kind: ScriptErrorKind,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScriptErrorKind {
+    Syntax(String),
+    Io {
+        file: String,
+        message: String,
+    },
+    /// The loaded program did not translate or assemble.
+    Load(String),
+    NotLoaded,
+    /// `ticktock` on VM code or `vmstep` on a Hack program.
+    WrongEmulator(&'static str),
+    UnknownVariable(String),
+}
+
+impl Display for ScriptErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScriptErrorKind::Syntax(message) => write!(f, "{message}"),
+            ScriptErrorKind::Io { file, message } => write!(f, "{file}: {message}"),
+            ScriptErrorKind::Load(message) => write!(f, "cannot load program:\n{message}"),
+            ScriptErrorKind::NotLoaded => write!(f, "no program is loaded"),
+            ScriptErrorKind::WrongEmulator(command) => {
+                write!(f, "`{command}` is not supported by the loaded program")
+            }
+            ScriptErrorKind::UnknownVariable(name) => write!(f, "unknown variable `{name}`"),
+        }
+    }
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl Error for ScriptError {}
+
+/// The first output line that differs from the `.cmp` file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mismatch {
+    /// 1-based line in the `.cmp` file.
+    pub line: usize,
+    /// 1-based column of the table.
+    pub column: usize,
+    /// Name of the output-list variable shown in the column.
+    pub name: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "comparison failure at line {}, column {} ({}): expected `{}`, got `{}`",
+            self.line, self.column, self.name, self.expected, self.actual
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ScriptReport {
+    /// File named by `output-file`, relative to the script.
+    pub output_file: Option<String>,
+    pub output: String,
+    pub mismatch: Option<Mismatch>,
+}
+
+/// A value readable and writable by `set` and `output-list`: `RAM[n]`, one
+/// of the pointers `sp`, `local`, `argument`, `this` and `that`, or an entry
+/// `local[n]`, `argument[n]`, `this[n]`, `that[n]` or `temp[n]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Variable {
+    name: String,
+}
+
+/// `%D1.6.1`: left padding, width and right padding of a decimal column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Format {
+    left: usize,
+    width: usize,
+    right: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ScriptCommand {
+    Load(Option<String>),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<(Variable, Format)>),
+    Set(Variable, i16),
+    Repeat(usize, Vec<(usize, ScriptCommand)>),
+    TickTock,
+    VmStep,
+    Output,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Word(String),
+    Separator,
+    Open,
+    Close,
+}
+
+fn tokenize(source: &str) -> Vec<(usize, Token)> {
+    let mut tokens = Vec::new();
+    let mut word = String::new();
+    let mut line = 1;
+    let mut chars = source.chars().peekable();
+    let flush = |word: &mut String, tokens: &mut Vec<(usize, Token)>, line| {
+        if !word.is_empty() {
+            tokens.push((line, Token::Word(std::mem::take(word))));
+        }
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if chars.peek() == Some(&'/') => {
+                flush(&mut word, &mut tokens, line);
+                while chars.next_if(|&c| c != '\n').is_some() {}
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                flush(&mut word, &mut tokens, line);
+                chars.next();
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            ',' | ';' | '{' | '}' => {
+                flush(&mut word, &mut tokens, line);
+                let token = match c {
+                    '{' => Token::Open,
+                    '}' => Token::Close,
+                    _ => Token::Separator,
+                };
+                tokens.push((line, token));
+            }
+            c if c.is_whitespace() => {
+                flush(&mut word, &mut tokens, line);
+                if c == '\n' {
+                    line += 1;
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    flush(&mut word, &mut tokens, line);
+    tokens
+}
+
+fn syntax_error(line: usize, message: String) -> ScriptError {
+    ScriptError {
+        line,
+        kind: ScriptErrorKind::Syntax(message),
+    }
+}
+
+fn parse_format(line: usize, column: &str) -> Result<(Variable, Format), ScriptError> {
+    let invalid = || syntax_error(line, format!("invalid output column `{column}`"));
+    let (name, format) = column.split_once("%D").ok_or_else(invalid)?;
+    let numbers: Vec<usize> = format
+        .split('.')
+        .map(|x| x.parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    let [left, width, right] = numbers[..] else {
+        return Err(invalid());
+    };
+    let variable = Variable {
+        name: String::from(name),
+    };
+    Ok((variable, Format { left, width, right }))
+}
+
+// `repeat` is the line of the `repeat` whose block is parsed, if any
+fn parse_commands(
+    tokens: &mut std::iter::Peekable<std::vec::IntoIter<(usize, Token)>>,
+    repeat: Option<usize>,
+) -> Result<Vec<(usize, ScriptCommand)>, ScriptError> {
+    let mut commands = Vec::new();
+    while let Some((line, token)) = tokens.next() {
+        let first = match token {
+            Token::Separator => continue,
+            Token::Close if repeat.is_some() => return Ok(commands),
+            Token::Word(word) => word,
+            _ => return Err(syntax_error(line, String::from("unexpected brace"))),
+        };
+        let mut words = Vec::new();
+        while let Some((_, Token::Word(_))) = tokens.peek() {
+            if let Some((_, Token::Word(word))) = tokens.next() {
+                words.push(word);
+            }
+        }
+
+        let expect_one = |words: &[String]| match words {
+            [word] => Ok(word.clone()),
+            _ => Err(syntax_error(
+                line,
+                format!("`{first}` expects one argument"),
+            )),
+        };
+        let command = match first.as_str() {
+            "load" if words.len() <= 1 => ScriptCommand::Load(words.first().cloned()),
+            "output-file" => ScriptCommand::OutputFile(expect_one(&words)?),
+            "compare-to" => ScriptCommand::CompareTo(expect_one(&words)?),
+            "output-list" => ScriptCommand::OutputList(
+                words
+                    .iter()
+                    .map(|x| parse_format(line, x))
+                    .collect::<Result<_, _>>()?,
+            ),
+            "set" => match &words[..] {
+                [name, value] => {
+                    let value = value
+                        .parse()
+                        .map_err(|_| syntax_error(line, format!("invalid value `{value}`")))?;
+                    let variable = Variable { name: name.clone() };
+                    ScriptCommand::Set(variable, value)
+                }
+                _ => {
+                    return Err(syntax_error(
+                        line,
+                        String::from("`set` expects a variable and a value"),
+                    ))
+                }
+            },
+            "repeat" => {
+                let count = expect_one(&words)?
+                    .parse()
+                    .map_err(|_| syntax_error(line, String::from("`repeat` expects a count")))?;
+                if !matches!(tokens.next(), Some((_, Token::Open))) {
+                    return Err(syntax_error(
+                        line,
+                        String::from("expected `{` after `repeat`"),
+                    ));
+                }
+                ScriptCommand::Repeat(count, parse_commands(tokens, Some(line))?)
+            }
+            "ticktock" if words.is_empty() => ScriptCommand::TickTock,
+            "vmstep" if words.is_empty() => ScriptCommand::VmStep,
+            "output" if words.is_empty() => ScriptCommand::Output,
+            _ => return Err(syntax_error(line, format!("unknown command `{first}`"))),
+        };
+        commands.push((line, command));
+    }
+    if let Some(line) = repeat {
+        return Err(syntax_error(line, String::from("unclosed `repeat` block")));
+    }
+    Ok(commands)
+}
+
+enum Emulator {
+    Cpu(Cpu),
+    Vm(Interpreter),
+}
+
+impl Emulator {
+    fn ram_mut(&mut self) -> &mut [i16] {
+        match self {
+            Emulator::Cpu(cpu) => cpu.ram_mut(),
+            Emulator::Vm(vm) => vm.ram_mut(),
+        }
+    }
+}
+
+// RAM address of `variable`
+fn address(ram: &[i16], variable: &Variable) -> Option<usize> {
+    let name = variable.name.as_str();
+    let pointer = |name: &str| match name {
+        "sp" => Some(0),
+        "local" => Some(1),
+        "argument" => Some(2),
+        "this" => Some(3),
+        "that" => Some(4),
+        _ => None,
+    };
+    let Some((base, index)) = name.strip_suffix(']').and_then(|x| x.split_once('[')) else {
+        return pointer(name);
+    };
+    let index: usize = index.parse().ok()?;
+    let address = match base {
+        "RAM" => index,
+        "temp" => 5 + index,
+        base => usize::from(ram[pointer(base)?] as u16) + index,
+    };
+    (address < emulator::MEMORY_SIZE).then_some(address)
+}
+
+struct Runner<'a> {
+    dir: &'a Path,
+    options: &'a Options,
+    emulator: Option<Emulator>,
+    columns: Vec<(Variable, Format)>,
+    compare: Option<Vec<String>>,
+    report: ScriptReport,
+    lines: usize,
+}
+
+impl Runner<'_> {
+    fn read(&self, line: usize, file: &str) -> Result<String, ScriptError> {
+        fs::read_to_string(self.dir.join(file)).map_err(|err| ScriptError {
+            line,
+            kind: ScriptErrorKind::Io {
+                file: String::from(file),
+                message: err.to_string(),
+            },
+        })
+    }
+
+    fn load(&mut self, line: usize, file: Option<&str>) -> Result<Emulator, ScriptError> {
+        let load_error = |message: String| ScriptError {
+            line,
+            kind: ScriptErrorKind::Load(message),
+        };
+        if let Some(file) = file.filter(|x| x.ends_with(".hack")) {
+            let words = emulator::parse_hack(&self.read(line, file)?)
+                .map_err(|err| load_error(err.to_string()))?;
+            let cpu = Cpu::new(&words).map_err(|err| load_error(err.to_string()))?;
+            return Ok(Emulator::Cpu(cpu));
+        }
+
+        let files: Vec<String> = match file {
+            Some(file) if file.ends_with(".vm") => vec![String::from(file)],
+            _ => {
+                let entries = fs::read_dir(self.dir)
+                    .map_err(|err| load_error(format!("{}: {}", self.dir.display(), err)))?;
+                let mut files: Vec<String> = entries
+                    .filter_map(|x| x.ok())
+                    .filter_map(|x| x.file_name().to_str().map(String::from))
+                    .filter(|x| x.ends_with(".vm"))
+                    .collect();
+                // in the same order the command line translates a directory
+                FileOrder::default()
+                    .sort(&mut files)
+                    .expect("Expected only explicit orders to fail");
+                files
+            }
+        };
+        let mut sources = Vec::new();
+        for file in files {
+            let source = self.read(line, &file)?;
+            sources.push((file, source));
+        }
+        let (program, diagnostics) =
+            translator::parse_program(&sources, self.options.static_naming);
+        if diagnostics.has_errors() {
+            return Err(load_error(diagnostics.to_string()));
+        }
+        if !file.is_some_and(|x| x.ends_with(".asm")) {
+            // like the VM emulator, start at Sys.init without a frame
+            let mut vm = Interpreter::new(&program);
+            vm.set_pc(vm.function(ENTRY_FUNCTION).unwrap_or(0));
+            return Ok(Emulator::Vm(vm));
+        }
+
+        // the chapter 7 scripts set up RAM themselves and expect no bootstrap
+        let options = Options {
+            bootstrap: BootstrapMode::detect(&program),
+            ..self.options.clone()
+        };
+        let asm = translator::write_program(&program, &options, Vec::new())
+            .expect("Expected writing to memory to succeed");
+        let words = assembler::assemble_source(&String::from_utf8_lossy(&asm))
+            .map_err(|err| load_error(err.to_string()))?;
+        let cpu = Cpu::new(&words).map_err(|err| load_error(err.to_string()))?;
+        Ok(Emulator::Cpu(cpu))
+    }
+
+    fn emulator(&mut self, line: usize) -> Result<&mut Emulator, ScriptError> {
+        self.emulator.as_mut().ok_or(ScriptError {
+            line,
+            kind: ScriptErrorKind::NotLoaded,
+        })
+    }
+
+    fn execute(&mut self, commands: &[(usize, ScriptCommand)]) -> Result<(), ScriptError> {
+        for (line, command) in commands {
+            if self.report.mismatch.is_some() {
+                return Ok(());
+            }
+            let line = *line;
+            match command {
+                ScriptCommand::Load(file) => {
+                    let emulator = self.load(line, file.as_deref())?;
+                    self.emulator = Some(emulator);
+                }
+                ScriptCommand::OutputFile(file) => self.report.output_file = Some(file.clone()),
+                ScriptCommand::CompareTo(file) => {
+                    let compare = self.read(line, file)?;
+                    self.compare = Some(compare.lines().map(String::from).collect());
+                }
+                ScriptCommand::OutputList(columns) => {
+                    self.columns = columns.clone();
+                    let header = columns
+                        .iter()
+                        .map(|(variable, format)| {
+                            let width = format.left + format.width + format.right;
+                            let name: String = variable.name.chars().take(width).collect();
+                            let padding = width - name.chars().count();
+                            let left = padding / 2;
+                            format!("{}{name}{}", " ".repeat(left), " ".repeat(padding - left))
+                        })
+                        .collect();
+                    self.write_row(header);
+                }
+                ScriptCommand::Set(variable, value) => {
+                    let ram = self.emulator(line)?.ram_mut();
+                    let address = address(ram, variable).ok_or_else(|| ScriptError {
+                        line,
+                        kind: ScriptErrorKind::UnknownVariable(variable.name.clone()),
+                    })?;
+                    ram[address] = *value;
+                }
+                ScriptCommand::Repeat(count, body) => {
+                    for _ in 0..*count {
+                        self.execute(body)?;
+                    }
+                }
+                ScriptCommand::TickTock => match self.emulator(line)? {
+                    Emulator::Cpu(cpu) => cpu.tick(),
+                    Emulator::Vm(_) => {
+                        return Err(ScriptError {
+                            line,
+                            kind: ScriptErrorKind::WrongEmulator("ticktock"),
+                        })
+                    }
+                },
+                ScriptCommand::VmStep => match self.emulator(line)? {
+                    Emulator::Vm(vm) => {
+                        vm.step();
+                    }
+                    Emulator::Cpu(_) => {
+                        return Err(ScriptError {
+                            line,
+                            kind: ScriptErrorKind::WrongEmulator("vmstep"),
+                        })
+                    }
+                },
+                ScriptCommand::Output => {
+                    let columns = self.columns.clone();
+                    let ram = self.emulator(line)?.ram_mut();
+                    let mut row = Vec::new();
+                    for (variable, format) in &columns {
+                        let address = address(ram, variable).ok_or_else(|| ScriptError {
+                            line,
+                            kind: ScriptErrorKind::UnknownVariable(variable.name.clone()),
+                        })?;
+                        // values too wide for the column are printed whole
+                        let value = ram[address].to_string();
+                        row.push(format!(
+                            "{}{value:>width$}{}",
+                            " ".repeat(format.left),
+                            " ".repeat(format.right),
+                            width = format.width
+                        ));
+                    }
+                    self.write_row(row);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // appends `|a|b|c|` to the output and compares it with the `.cmp` file
+    fn write_row(&mut self, cells: Vec<String>) {
+        let row = format!("|{}|", cells.join("|"));
+        self.report.output.push_str(&row);
+        self.report.output.push('\n');
+        self.lines += 1;
+
+        let Some(compare) = &self.compare else {
+            return;
+        };
+        let expected_line = compare.get(self.lines - 1).map_or("", String::as_str);
+        let expected: Vec<&str> = expected_line.trim().trim_matches('|').split('|').collect();
+        for (i, actual) in cells.iter().enumerate() {
+            let expected = expected.get(i).copied().unwrap_or("");
+            if expected.trim() != actual.trim() {
+                let name = self.columns.get(i).map_or("", |(x, _)| x.name.as_str());
+                self.report.mismatch = Some(Mismatch {
+                    line: self.lines,
+                    column: i + 1,
+                    name: String::from(name),
+                    expected: String::from(expected.trim()),
+                    actual: String::from(actual.trim()),
+                });
+                return;
+            }
+        }
+    }
+}
+
+/// Runs the script `source`, resolving the files it names against `dir`.
+/// Programs loaded as `.asm` are translated with `options`. Execution stops at
+/// the first output line that differs from the `compare-to` file.
+pub fn run_script(
+    source: &str,
+    dir: &Path,
+    options: &Options,
+) -> Result<ScriptReport, ScriptError> {
+    let mut tokens = tokenize(source).into_iter().peekable();
+    let commands = parse_commands(&mut tokens, None)?;
+    let mut runner = Runner {
+        dir,
+        options,
+        emulator: None,
+        columns: Vec::new(),
+        compare: None,
+        report: ScriptReport::default(),
+        lines: 0,
+    };
+    runner.execute(&commands)?;
+    Ok(runner.report)
+}
+
+/// Reads and runs the `.tst` file at `path`.
+pub fn run_script_file(path: &Path, options: &Options) -> Result<ScriptReport, ScriptError> {
+    let source = fs::read_to_string(path).map_err(|err| ScriptError {
+        line: 0,
+        kind: ScriptErrorKind::Io {
+            file: path.display().to_string(),
+            message: err.to_string(),
+        },
+    })?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    run_script(&source, dir, options)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{parse_commands, run_script, tokenize, Format, Mismatch, ScriptCommand, Variable};
+    use crate::translator::Options;
+
+    #[test]
+    fn parses_scripts() {
+        let source = "load Foo.asm, // comment\n/* block\n comment */ set RAM[0] -256,\n\
+                      repeat 2 {\n  ticktock;\n}\noutput-list RAM[0]%D1.6.1;\noutput;\n";
+        let commands = parse_commands(&mut tokenize(source).into_iter().peekable(), None);
+        let ram = Variable {
+            name: String::from("RAM[0]"),
+        };
+        assert_eq!(
+            commands,
+            Ok(vec![
+                (1, ScriptCommand::Load(Some(String::from("Foo.asm")))),
+                (3, ScriptCommand::Set(ram.clone(), -256)),
+                (
+                    4,
+                    ScriptCommand::Repeat(2, vec![(5, ScriptCommand::TickTock)])
+                ),
+                (
+                    7,
+                    ScriptCommand::OutputList(vec![(
+                        ram,
+                        Format {
+                            left: 1,
+                            width: 6,
+                            right: 1
+                        }
+                    )])
+                ),
+                (8, ScriptCommand::Output),
+            ])
+        );
+    }
+
+    #[test]
+    fn runs_sample_scripts() {
+        let dir = Path::new("test/StackArithmetic/SimpleAdd");
+        for script in ["SimpleAdd.tst", "SimpleAddVME.tst"] {
+            let source = std::fs::read_to_string(dir.join(script)).unwrap();
+            let report = run_script(&source, dir, &Options::default()).unwrap();
+            assert_eq!(report.mismatch, None, "{script}");
+            assert_eq!(report.output_file.as_deref(), Some("SimpleAdd.out"));
+            assert_eq!(
+                report.output,
+                "|  RAM[0]  | RAM[256] |\n|     257  |      15  |\n"
+            );
+        }
+
+        let source = "load SimpleAdd.asm, compare-to SimpleAdd.cmp, set RAM[0] 300,\n\
+                      repeat 60 { ticktock; }\noutput-list RAM[0]%D2.6.2 RAM[256]%D1.6.1;\noutput;";
+        let report = run_script(source, dir, &Options::default()).unwrap();
+        assert_eq!(
+            report.mismatch,
+            Some(Mismatch {
+                line: 2,
+                column: 1,
+                name: String::from("RAM[0]"),
+                expected: String::from("257"),
+                actual: String::from("301"),
+            })
+        );
+    }
+
+    #[test]
+    fn formats_columns() {
+        let dir = Path::new("test/StackArithmetic/SimpleAdd");
+        let source = "load SimpleAdd.asm, set RAM[0] -12345,\n\
+                      output-list RAM[0]%D1.5.1 RAM[0]%D1.6.1;\noutput;\noutput-list \u{e9}t\u{e9}%D1.3.1;";
+        let report = run_script(source, dir, &Options::default()).unwrap();
+        assert_eq!(
+            report.output,
+            "|RAM[0] | RAM[0] |\n| -12345 | -12345 |\n| \u{e9}t\u{e9} |\n"
+        );
+
+        let err = run_script(
+            "load SimpleAdd.asm,\nrepeat 2 {\n  ticktock;\n",
+            dir,
+            &Options::default(),
+        )
+        .unwrap_err();
+        assert_eq!(err.line, 2);
+    }
+}
@@ -0,0 +1,255 @@
+//! Maps every instruction of the generated assembly back to the VM command it
+//! was written for, so tools running the Hack code can show which command is
+//! executing.
+//!
+//! `HackAsmBackend` starts the code of every command with a `// command`
+//! comment and the optimizer keeps comments in place, so the map is recovered
+//! from the assembly itself by pairing the comments with the program's
+//! statements.
+
+use std::fmt::Write;
+
+use crate::{
+    asm::Instruction,
+    ir::Program,
+    parser::{Command, Span},
+};
+
+/// Where the VM command behind an instruction is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    /// The enclosing function, `None` for top-level code.
+    pub function: Option<String>,
+}
+
+/// One entry per instruction in ROM order. Instructions of the bootstrap code
+/// and the shared runtime have no origin.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SourceMap {
+    pub entries: Vec<(u16, Option<Origin>)>,
+}
+
+impl SourceMap {
+    /// Builds the map of `asm`, written by `HackAsmBackend` for `program` with
+    /// any options.
+    pub fn build(program: &Program, asm: &str) -> SourceMap {
+        let mut commands = commands(program).into_iter();
+        let mut map = SourceMap::default();
+        let mut origin = None;
+        for line in asm.lines() {
+            let instruction: Instruction = line
+                .parse()
+                .expect("Expected HackAsmBackend to emit valid assembly");
+            match instruction {
+                Instruction::Comment(x) => origin = next_origin(&mut commands, &x),
+                x if x.is_code() => {
+                    let address = map.entries.len() as u16;
+                    map.entries.push((address, origin.clone()));
+                }
+                _ => {}
+            }
+        }
+        map
+    }
+
+    /// The map as a JSON object with one entry per instruction, the fields of
+    /// instructions without an origin being `null`.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\n  \"version\": 1,\n  \"entries\": [");
+        for (i, (address, origin)) in self.entries.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let null = || String::from("null");
+            let (file, line, column, function) = match origin {
+                Some(x) => (
+                    json_string(&x.file),
+                    x.line.to_string(),
+                    x.column.to_string(),
+                    x.function.as_deref().map_or_else(null, json_string),
+                ),
+                None => (null(), null(), null(), null()),
+            };
+            write!(
+                json,
+                "{separator}\n    {{\"address\": {address}, \"file\": {file}, \"line\": {line}, \
+                 \"column\": {column}, \"function\": {function}}}"
+            )
+            .expect("Expected writing to a String to succeed");
+        }
+        json.push_str("\n  ]\n}\n");
+        json
+    }
+}
+
+/// Every command `HackAsmBackend` starts with a comment, in the order it writes
+/// them, function declarations included.
+pub(crate) fn commands(program: &Program) -> Vec<(Command, Origin)> {
+    let mut commands = Vec::new();
+    for module in &program.modules {
+        let origin = |span: Span, function: Option<&str>| Origin {
+            file: module.file.clone(),
+            line: span.line,
+            column: span.column,
+            function: function.map(String::from),
+        };
+        for statement in &module.top_level {
+            commands.push((statement.command.clone(), origin(statement.span, None)));
+        }
+        for function in &module.functions {
+            let name = Some(function.name.as_str());
+            let command = Command::Function(function.name.clone(), function.n_vars);
+            commands.push((command, origin(function.span, name)));
+            for statement in &function.body {
+                commands.push((statement.command.clone(), origin(statement.span, name)));
+            }
+        }
+    }
+    commands
+}
+
+/// The origin of the code following the assembly comment `comment`, taking
+/// the next of `commands` unless it marks the bootstrap or the runtime.
+pub(crate) fn next_origin(
+    commands: &mut impl Iterator<Item = (Command, Origin)>,
+    comment: &str,
+) -> Option<Origin> {
+    if comment == "bootstrap" || comment == "runtime" {
+        return None;
+    }
+    let (command, origin) = commands
+        .next()
+        .expect("Expected a statement for every command comment");
+    assert_eq!(
+        comment,
+        command.to_string(),
+        "Expected comments in program order"
+    );
+    Some(origin)
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::from("\"");
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if u32::from(c) < 0x20 => json.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Origin, SourceMap};
+    use crate::translator::{parse_program, write_program, BootstrapMode, Options, StaticNaming};
+
+    const SOURCE: &str = "push constant 1\n\
+                          function Main.f 1\n  push local 0\n  push constant 2\n  add\n  return\n";
+
+    fn map(options: &Options) -> SourceMap {
+        let (program, diagnostics) = parse_program(&[("dir/Main.vm", SOURCE)], StaticNaming::Stem);
+        assert!(!diagnostics.has_errors());
+        let asm = write_program(&program, options, Vec::new()).unwrap();
+        SourceMap::build(&program, &String::from_utf8(asm).unwrap())
+    }
+
+    fn origin(line: usize, column: usize, function: Option<&str>) -> Option<Origin> {
+        Some(Origin {
+            file: String::from("dir/Main.vm"),
+            line,
+            column,
+            function: function.map(String::from),
+        })
+    }
+
+    #[test]
+    fn maps_instructions_to_commands() {
+        let options = Options {
+            bootstrap: BootstrapMode::None,
+            ..Options::default()
+        };
+        let map = map(&options);
+        let origins: Vec<_> = map.entries.iter().map(|(_, x)| x.clone()).collect();
+        // push constant 1
+        assert_eq!(origins[..7], vec![origin(1, 1, None); 7]);
+        // the function declaration, pointing at the name
+        assert_eq!(origins[7], origin(2, 10, Some("Main.f")));
+        assert_eq!(origins.last().unwrap(), &origin(6, 3, Some("Main.f")));
+        assert!(map
+            .entries
+            .iter()
+            .enumerate()
+            .all(|(i, (x, _))| usize::from(*x) == i));
+
+        // the bootstrap and the shared runtime are nobody's
+        let (program, _) = parse_program(
+            &[("Sys.vm", "function Sys.init 0\n"), ("dir/Main.vm", SOURCE)],
+            StaticNaming::Stem,
+        );
+        let build = |options: &Options| {
+            let asm = write_program(&program, options, Vec::new()).unwrap();
+            SourceMap::build(&program, &String::from_utf8(asm).unwrap())
+        };
+        let map = build(&Options::default());
+        assert_eq!(map.entries[0].1, None);
+        assert_eq!(map.entries.last().unwrap().1, origin(6, 3, Some("Main.f")));
+        let map = build(&Options {
+            shared_runtime: true,
+            bootstrap: BootstrapMode::None,
+            ..Options::default()
+        });
+        // Sys.init has no locals to set up, so Main.vm comes first
+        assert_eq!(map.entries[0].1, origin(1, 1, None));
+        assert_eq!(map.entries.last().unwrap().1, None);
+    }
+
+    #[test]
+    fn survives_optimization() {
+        let options = Options {
+            optimize: true,
+            bootstrap: BootstrapMode::None,
+            ..Options::default()
+        };
+        let optimized = map(&options);
+        let plain = map(&Options {
+            optimize: false,
+            ..options
+        });
+        assert!(optimized.entries.len() < plain.entries.len());
+        assert_eq!(optimized.entries[0].1, origin(1, 1, None));
+        assert_eq!(
+            optimized.entries.last().unwrap().1,
+            origin(6, 3, Some("Main.f"))
+        );
+    }
+
+    #[test]
+    fn writes_json() {
+        let map = SourceMap {
+            entries: vec![
+                (0, None),
+                (
+                    1,
+                    Some(Origin {
+                        file: String::from("dir\\\"Main\".vm"),
+                        line: 3,
+                        column: 5,
+                        function: Some(String::from("Main.f")),
+                    }),
+                ),
+            ],
+        };
+        assert_eq!(
+            map.to_json(),
+            "{\n  \"version\": 1,\n  \"entries\": [\n    \
+             {\"address\": 0, \"file\": null, \"line\": null, \"column\": null, \"function\": null},\n    \
+             {\"address\": 1, \"file\": \"dir\\\\\\\"Main\\\".vm\", \"line\": 3, \"column\": 5, \
+             \"function\": \"Main.f\"}\n  ]\n}\n"
+        );
+    }
+}
@@ -0,0 +1,296 @@
+//! Symbol files: where the functions, labels and statics of a translated
+//! program ended up in ROM and RAM.
+//!
+//! A symbol file has one `ADDRESS KIND NAME` line per symbol, ROM symbols
+//! first, then the statics, each by address and kind. Blank lines and lines
+//! starting with `//` are ignored when reading it back.
+
+use std::{
+    collections::HashSet,
+    error::Error,
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+use crate::{
+    assembler::{self, AssembleError},
+    ir::Program,
+    parser::Command,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SymbolKind {
+    /// Entry point of a VM function.
+    Function,
+    /// A VM `label`, as `function$label`.
+    Label,
+    /// Where a call returns to, as `callee$ret.n`.
+    Return,
+    /// Internal label of an inline `eq`, `gt` or `lt`.
+    Compare,
+    /// The shared routines and the halting loop, named `$$...`.
+    Runtime,
+    /// RAM address of a `static` variable.
+    Static,
+}
+
+const KINDS: [(SymbolKind, &str); 6] = [
+    (SymbolKind::Function, "function"),
+    (SymbolKind::Label, "label"),
+    (SymbolKind::Return, "return"),
+    (SymbolKind::Compare, "compare"),
+    (SymbolKind::Runtime, "runtime"),
+    (SymbolKind::Static, "static"),
+];
+
+impl Display for SymbolKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (_, name) = KINDS.iter().find(|(kind, _)| kind == self).unwrap();
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for SymbolKind {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        KINDS
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(kind, _)| *kind)
+            .ok_or(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub address: u16,
+    pub kind: SymbolKind,
+    pub name: String,
+}
+
+/// A line of a symbol file that is not `ADDRESS KIND NAME`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SymbolError {
+    pub line: usize,
+    pub text: String,
+}
+
+impl Display for SymbolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: `{}` is not a symbol", self.line, self.text)
+    }
+}
+
+impl Error for SymbolError {}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new(mut symbols: Vec<Symbol>) -> SymbolTable {
+        symbols.sort_by(|a, b| {
+            let key = |x: &Symbol| (x.kind == SymbolKind::Static, x.address, x.kind);
+            key(a).cmp(&key(b)).then_with(|| a.name.cmp(&b.name))
+        });
+        SymbolTable { symbols }
+    }
+
+    /// Resolves the symbols of `asm`, written by `HackAsmBackend` for
+    /// `program`, and sorts them by what they stand for. The predefined
+    /// symbols are left out.
+    pub fn build(program: &Program, asm: &str) -> Result<SymbolTable, AssembleError> {
+        let instructions = assembler::parse(asm)?;
+        let labels = assembler::resolve_labels(&instructions)?;
+        let predefined = assembler::predefined_symbols();
+
+        let functions: HashSet<&str> = program.functions().map(|x| x.name.as_str()).collect();
+        let vm_labels: HashSet<String> = program
+            .modules
+            .iter()
+            .flat_map(|x| x.statements())
+            .filter_map(|(function, statement)| match &statement.command {
+                Command::Label(label) => Some(format!("{}${label}", function.unwrap_or(""))),
+                _ => None,
+            })
+            .collect();
+
+        let symbols = assembler::resolve_symbols(&instructions)?
+            .into_iter()
+            .filter(|(name, _)| !predefined.contains_key(name))
+            .map(|(name, address)| {
+                let kind = if !labels.contains_key(&name) {
+                    SymbolKind::Static
+                } else if functions.contains(name.as_str()) {
+                    SymbolKind::Function
+                } else if vm_labels.contains(&name) {
+                    SymbolKind::Label
+                } else if name.starts_with("$$") {
+                    SymbolKind::Runtime
+                } else if name.contains("$ret.") {
+                    SymbolKind::Return
+                } else {
+                    SymbolKind::Compare
+                };
+                Symbol {
+                    address,
+                    kind,
+                    name,
+                }
+            })
+            .collect();
+        Ok(SymbolTable::new(symbols))
+    }
+
+    /// Reads a symbol file back.
+    pub fn parse(text: &str) -> Result<SymbolTable, SymbolError> {
+        let mut symbols = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let symbol = match fields[..] {
+                [address, kind, name] => {
+                    address
+                        .parse()
+                        .ok()
+                        .zip(kind.parse().ok())
+                        .map(|(address, kind)| Symbol {
+                            address,
+                            kind,
+                            name: String::from(name),
+                        })
+                }
+                _ => None,
+            };
+            symbols.push(symbol.ok_or_else(|| SymbolError {
+                line: i + 1,
+                text: String::from(line),
+            })?);
+        }
+        Ok(SymbolTable::new(symbols))
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Symbol> {
+        self.symbols.iter().find(|x| x.name == name)
+    }
+
+    /// The function whose code holds ROM `address`, if it is past the first
+    /// function entry.
+    pub fn function_at(&self, address: u16) -> Option<&str> {
+        self.symbols
+            .iter()
+            .filter(|x| x.kind == SymbolKind::Function && x.address <= address)
+            .max_by_key(|x| x.address)
+            .map(|x| x.name.as_str())
+    }
+
+    /// The static stored at RAM `address`.
+    pub fn static_at(&self, address: u16) -> Option<&str> {
+        self.symbols
+            .iter()
+            .find(|x| x.kind == SymbolKind::Static && x.address == address)
+            .map(|x| x.name.as_str())
+    }
+}
+
+impl Display for SymbolTable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for symbol in &self.symbols {
+            writeln!(f, "{} {} {}", symbol.address, symbol.kind, symbol.name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Symbol, SymbolError, SymbolKind, SymbolTable};
+    use crate::translator::{parse_program, write_program, Options, StaticNaming};
+
+    fn build(options: &Options) -> SymbolTable {
+        let sources = [
+            (
+                "Sys.vm",
+                "function Sys.init 0\ncall Main.main 0\nlabel HALT\ngoto HALT\n",
+            ),
+            (
+                "Main.vm",
+                "function Main.main 0\npush static 1\npush static 0\nlt\n\
+                 pop static 0\npush constant 0\nreturn\n",
+            ),
+        ];
+        let (program, diagnostics) = parse_program(&sources, StaticNaming::Stem);
+        assert!(!diagnostics.has_errors());
+        let asm = write_program(&program, options, Vec::new()).unwrap();
+        SymbolTable::build(&program, &String::from_utf8(asm).unwrap()).unwrap()
+    }
+
+    fn kind(table: &SymbolTable, name: &str) -> Option<SymbolKind> {
+        table.get(name).map(|x| x.kind)
+    }
+
+    #[test]
+    fn classifies_symbols() {
+        let table = build(&Options::default());
+        assert_eq!(kind(&table, "Sys.init"), Some(SymbolKind::Function));
+        assert_eq!(kind(&table, "Main.main"), Some(SymbolKind::Function));
+        assert_eq!(kind(&table, "Sys.init$HALT"), Some(SymbolKind::Label));
+        assert_eq!(kind(&table, "Sys.init$ret.0"), Some(SymbolKind::Return));
+        assert_eq!(kind(&table, "Main.main$ret.1"), Some(SymbolKind::Return));
+        assert_eq!(kind(&table, "CMP.0"), Some(SymbolKind::Compare));
+        assert_eq!(kind(&table, "SP"), None);
+        // statics get RAM in the order they first appear
+        assert_eq!(table.get("Main.1").unwrap().address, 16);
+        assert_eq!(table.get("Main.0").unwrap().address, 17);
+        assert_eq!(table.static_at(17), Some("Main.0"));
+
+        let main = table.get("Main.main").unwrap().address;
+        assert_eq!(table.function_at(main + 3), Some("Main.main"));
+        assert_eq!(table.function_at(0), None);
+
+        let table = build(&Options {
+            shared_runtime: true,
+            ..Options::default()
+        });
+        assert_eq!(kind(&table, "$$lt"), Some(SymbolKind::Runtime));
+        assert_eq!(kind(&table, "CMP.0"), None);
+    }
+
+    #[test]
+    fn round_trips_through_text() {
+        let table = build(&Options::default());
+        let text = table.to_string();
+        // the bootstrap returns right before Sys.init
+        let entry = table.get("Sys.init").unwrap().address;
+        assert!(text.starts_with(&format!(
+            "{entry} function Sys.init\n{entry} return Sys.init$ret.0\n"
+        )));
+        assert!(text.ends_with("16 static Main.1\n17 static Main.0\n"));
+        assert_eq!(SymbolTable::parse(&text), Ok(table));
+
+        assert_eq!(
+            SymbolTable::parse("// Main\n\n42 function Main.main\n"),
+            Ok(SymbolTable::new(vec![Symbol {
+                address: 42,
+                kind: SymbolKind::Function,
+                name: String::from("Main.main"),
+            }]))
+        );
+        assert_eq!(
+            SymbolTable::parse("42 function Main.main\n42 procedure Main.f\n"),
+            Err(SymbolError {
+                line: 2,
+                text: String::from("42 procedure Main.f"),
+            })
+        );
+    }
+}
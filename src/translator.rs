@@ -0,0 +1,454 @@
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::{
+    asm::Instruction,
+    backend::{self, Backend},
+    code_writer::HackAsmBackend,
+    diagnostics::Diagnostics,
+    ir::Program,
+    linker::{check_entry, check_statics, link, ENTRY_FUNCTION},
+    parser::Parser,
+    validate::validate,
+};
+
+/// Settings controlling how a program is translated.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Run the peephole optimizer over the generated assembly.
+    pub optimize: bool,
+    /// Jump to one shared copy of call, return and the comparisons instead
+    /// of inlining them at every site.
+    pub shared_runtime: bool,
+    pub bootstrap: BootstrapMode,
+    pub static_naming: StaticNaming,
+}
+
+/// What the generated code does before the first command.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum BootstrapMode {
+    /// Nothing: the program starts at its first command with RAM as the
+    /// test script sets it, and halts once it runs off its end.
+    None,
+    /// Sets SP to 256 and calls `Sys.init`.
+    #[default]
+    Standard,
+    /// Sets the segment pointers to the given values and calls a function,
+    /// which must be defined as for `Standard`.
+    Custom(Bootstrap),
+}
+
+/// Initial state for [`BootstrapMode::Custom`]. Values must fit in an
+/// A-instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Bootstrap {
+    pub sp: u16,
+    pub lcl: u16,
+    pub arg: u16,
+    pub this: u16,
+    pub that: u16,
+    pub entry: String,
+}
+
+impl BootstrapMode {
+    /// `Standard` when `program` has a `Sys.vm` file, `None` otherwise, as the
+    /// chapter 7 samples come without one and expect no bootstrap code.
+    pub fn detect(program: &Program) -> BootstrapMode {
+        let has_sys = program
+            .modules
+            .iter()
+            .any(|x| Path::new(&x.file).file_name().and_then(|x| x.to_str()) == Some("Sys.vm"));
+        if has_sys {
+            BootstrapMode::Standard
+        } else {
+            BootstrapMode::None
+        }
+    }
+
+    /// The function called after setting the pointers, if any.
+    pub fn entry(&self) -> Option<&str> {
+        match self {
+            BootstrapMode::None => None,
+            BootstrapMode::Standard => Some(ENTRY_FUNCTION),
+            BootstrapMode::Custom(bootstrap) => Some(&bootstrap.entry),
+        }
+    }
+}
+
+/// How the assembler variables backing the `static` segment of a file are
+/// named.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum StaticNaming {
+    /// `Foo.i` for `static i` of `Foo.vm`, as the book specifies.
+    #[default]
+    Stem,
+    /// `Foo.vm.i`, keeping the extension.
+    FileName,
+}
+
+impl StaticNaming {
+    /// The part of the variable names before the index for the statics of
+    /// `file`, a path.
+    pub fn prefix(self, file: &str) -> String {
+        let path = Path::new(file);
+        let name = match self {
+            StaticNaming::Stem => path.file_stem(),
+            StaticNaming::FileName => path.file_name(),
+        };
+        String::from(name.and_then(|x| x.to_str()).unwrap_or(""))
+    }
+
+    /// The assembler variable holding `static index` of `file`.
+    pub fn symbol(self, file: &str, index: usize) -> String {
+        format!("{}.{index}", self.prefix(file))
+    }
+}
+
+/// The order the `.vm` files of a directory are translated in. It only
+/// changes the layout of the output, execution starts at `Sys.init` anyway.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum FileOrder {
+    /// `Sys.vm` first, then the others by name.
+    #[default]
+    SysFirst,
+    /// By file name.
+    Name,
+    /// The named files first, in the given order, then the others by name.
+    Explicit(Vec<String>),
+}
+
+impl FileOrder {
+    /// Sorts `files` by their file names, in byte order so the result does
+    /// not depend on the locale or the file system. Fails with the first file
+    /// of an explicit order that is not among `files`.
+    pub fn sort<P: AsRef<Path>>(&self, files: &mut [P]) -> Result<(), String> {
+        let name = |file: &P| {
+            let name = file.as_ref().file_name().and_then(|x| x.to_str());
+            String::from(name.unwrap_or(""))
+        };
+        if let FileOrder::Explicit(names) = self {
+            if let Some(missing) = names.iter().find(|x| !files.iter().any(|f| name(f) == **x)) {
+                return Err(missing.clone());
+            }
+        }
+        files.sort_by_cached_key(|file| {
+            let name = name(file);
+            let rank = match self {
+                FileOrder::SysFirst => usize::from(name != "Sys.vm"),
+                FileOrder::Name => 0,
+                FileOrder::Explicit(names) => {
+                    names.iter().position(|x| *x == name).unwrap_or(names.len())
+                }
+            };
+            (rank, name)
+        });
+        Ok(())
+    }
+}
+
+/// Parses, validates and links `sources`, given as `(file name, text)` pairs,
+/// checking their statics can be named with `naming`. The program is returned
+/// even when it has errors so callers can decide what to do with the
+/// diagnostics.
+pub fn parse_program<N, T>(sources: &[(N, T)], naming: StaticNaming) -> (Program, Diagnostics)
+where
+    N: AsRef<str>,
+    T: AsRef<str>,
+{
+    let mut diagnostics = Diagnostics::new();
+    let mut program = Program::default();
+    for (name, text) in sources {
+        let (name, text) = (name.as_ref(), text.as_ref());
+        diagnostics.add_source(name, text);
+
+        let (module, errors) = Parser::build(name, text)
+            .expect("Expected Parser::build() successfully")
+            .parse_module();
+        for err in errors {
+            diagnostics.push(err);
+        }
+        for err in validate(&module) {
+            diagnostics.push(err);
+        }
+        program.modules.push(module);
+    }
+
+    for err in link(&program)
+        .into_iter()
+        .chain(check_statics(&program, naming))
+    {
+        diagnostics.push(err);
+    }
+
+    (program, diagnostics)
+}
+
+/// Writes an already checked `program` with `backend`, returning its output.
+pub fn emit<B: Backend>(program: &Program, mut backend: B) -> io::Result<B::Output> {
+    backend::write_program(&mut backend, program)?;
+    backend.finish()
+}
+
+/// Writes the assembly for an already checked `program` to `out`, returning
+/// the writer once all output has been flushed.
+pub fn write_program<W: Write>(program: &Program, options: &Options, out: W) -> io::Result<W> {
+    emit(program, HackAsmBackend::new(out, options)?)
+}
+
+/// Number of instructions `program` occupies in ROM when written with `options`.
+pub fn code_size(program: &Program, options: &Options) -> usize {
+    let asm =
+        write_program(program, options, Vec::new()).expect("Expected writing to memory to succeed");
+    String::from_utf8_lossy(&asm)
+        .lines()
+        .filter_map(|x| x.parse::<Instruction>().ok())
+        .filter(Instruction::is_code)
+        .count()
+}
+
+/// Translates `sources`, given as `(file name, text)` pairs, into Hack assembly.
+/// Fails with every error found if any of the sources is invalid or the
+/// bootstrap code would call an undefined function.
+pub fn translate<N, T>(sources: &[(N, T)], options: &Options) -> Result<String, Diagnostics>
+where
+    N: AsRef<str>,
+    T: AsRef<str>,
+{
+    let (program, mut diagnostics) = parse_program(sources, options.static_naming);
+    if let Some(err) = check_entry(&program, &options.bootstrap) {
+        diagnostics.push(err);
+    }
+    if diagnostics.has_errors() {
+        return Err(diagnostics);
+    }
+
+    let asm = write_program(&program, options, Vec::new())
+        .expect("Expected writing to memory to succeed");
+    Ok(String::from_utf8(asm).expect("Expected generated assembly to be UTF-8"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+
+    use super::{
+        parse_program, translate, write_program, Bootstrap, BootstrapMode, FileOrder, Options,
+        StaticNaming,
+    };
+    use crate::{assembler::assemble_source, emulator::Cpu};
+
+    #[test]
+    fn translates_in_memory() {
+        let sources = [("Main.vm", "push constant 7\npush constant 8\nadd\n")];
+        let options = Options {
+            bootstrap: BootstrapMode::None,
+            ..Options::default()
+        };
+        let asm = translate(&sources, &options).unwrap();
+        assert!(asm.starts_with("// push constant 7\n@7\nD=A\n"));
+        assert!(asm.contains("// push constant 7\n@7\nD=A\n"));
+        assert!(asm.ends_with("// add\n@SP\nM=M-1\nA=M\nD=M\n@R13\nM=D\n@SP\nM=M-1\nA=M\nD=M\n@R13\nD=D+M\n@SP\nA=M\nM=D\n@SP\nM=M+1\n"));
+    }
+
+    // a writer with room for this many more bytes
+    struct Full(usize);
+
+    impl Write for Full {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if buf.len() > self.0 {
+                return Err(io::Error::new(io::ErrorKind::StorageFull, "full"));
+            }
+            self.0 -= buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn requires_the_entry_function() {
+        let sources = [("Main.vm", "push constant 7\n")];
+        let diagnostics = translate(&sources, &Options::default()).unwrap_err();
+        assert_eq!(diagnostics.error_count(), 1);
+        assert_eq!(
+            diagnostics.to_string(),
+            "error[E0209]: bootstrap calls `Sys.init`, which is not defined\n\n\
+             error: aborting due to 1 previous error\n"
+        );
+
+        let sources = [("Sys.vm", "function Sys.init 0\n")];
+        assert!(translate(&sources, &Options::default()).is_ok());
+    }
+
+    #[test]
+    fn reports_write_errors() {
+        let (program, _) = parse_program(
+            &[("Main.vm", "push constant 1\n".repeat(1000))],
+            StaticNaming::Stem,
+        );
+        for optimize in [false, true] {
+            let options = Options {
+                optimize,
+                ..Options::default()
+            };
+            let size = write_program(&program, &options, Vec::new()).unwrap().len();
+            // failing within the commands and in the last flush
+            for room in [0, size - 1] {
+                let err = write_program(&program, &options, Full(room)).err().unwrap();
+                assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+            }
+            assert!(write_program(&program, &options, Full(size)).is_ok());
+        }
+    }
+
+    #[test]
+    fn reports_diagnostics() {
+        let sources = [("Main.vm", "push constant 7\npop constant 8\nfoo\n")];
+        let options = Options {
+            bootstrap: BootstrapMode::None,
+            ..Options::default()
+        };
+        let diagnostics = translate(&sources, &options).unwrap_err();
+        assert_eq!(diagnostics.error_count(), 2);
+    }
+
+    #[test]
+    fn shares_runtime_routines() {
+        let sources = [(
+            "Main.vm",
+            "function Sys.init 0\ncall Main.f 0\ncall Main.f 0\nreturn\n\
+             function Main.f 0\npush constant 1\npush constant 2\nlt\nreturn\n",
+        )];
+        let options = Options {
+            shared_runtime: true,
+            ..Options::default()
+        };
+        let asm = translate(&sources, &options).unwrap();
+        assert_eq!(asm.matches("($$call)").count(), 1);
+        assert_eq!(asm.matches("@$$call\n").count(), 3);
+        assert_eq!(asm.matches("@$$return\n").count(), 2);
+        assert_eq!(asm.matches("@$$lt\n").count(), 1);
+        assert!(!asm.contains("(CMP.0)"));
+    }
+
+    #[test]
+    fn writes_the_requested_bootstrap() {
+        let run = |source: &str, bootstrap| {
+            let options = Options {
+                shared_runtime: true,
+                bootstrap,
+                ..Options::default()
+            };
+            let asm = translate(&[("Main.vm", source)], &options).unwrap();
+            let mut cpu = Cpu::new(&assemble_source(&asm).unwrap()).unwrap();
+            cpu.run(1000);
+            assert!(cpu.is_halted());
+            (asm, cpu)
+        };
+
+        // runs off the top-level code into the halting loop; SP is never set
+        // so the constant is pushed onto SP itself and then incremented
+        let (asm, cpu) = run("push constant 7\n", BootstrapMode::None);
+        assert!(asm.starts_with("// push constant 7\n"));
+        assert!(asm.contains("($$halt)\n@$$halt\n0;JMP\n($$call)\n"));
+        assert_eq!(cpu.ram()[0], 8);
+
+        let source = "function Main.main 0\npush constant 9\npop this 1\nlabel END\ngoto END\n";
+        let (asm, cpu) = run(
+            source,
+            BootstrapMode::Custom(Bootstrap {
+                sp: 300,
+                lcl: 310,
+                arg: 320,
+                this: 3000,
+                that: 4000,
+                entry: String::from("Main.main"),
+            }),
+        );
+        assert!(asm.starts_with("// bootstrap\n@300\nD=A\n@SP\nM=D\n@310\n"));
+        assert!(!asm.contains("$$halt"));
+        // the frame of the call to Main.main sits on top of the given pointers
+        assert_eq!(cpu.ram()[0], 305);
+        assert_eq!(cpu.ram()[301..305], [310, 320, 3000, 4000]);
+        assert_eq!(cpu.ram()[2], 300);
+        assert_eq!(cpu.ram()[3001], 9);
+
+        let options = Options {
+            bootstrap: BootstrapMode::Custom(Bootstrap {
+                entry: String::from("Main.mian"),
+                sp: 300,
+                lcl: 310,
+                arg: 320,
+                this: 3000,
+                that: 4000,
+            }),
+            ..Options::default()
+        };
+        let diagnostics = translate(&[("Main.vm", source)], &options).unwrap_err();
+        assert!(diagnostics
+            .to_string()
+            .starts_with("error[E0209]: bootstrap calls `Main.mian`, which is not defined\n"));
+    }
+
+    #[test]
+    fn names_statics() {
+        let sources = [("dir/Main.vm", "push constant 1\npop static 2\n")];
+        let stem = Options {
+            bootstrap: BootstrapMode::None,
+            ..Options::default()
+        };
+        let asm = translate(&sources, &stem).unwrap();
+        assert!(asm.contains("@Main.2\n"));
+        let options = Options {
+            static_naming: StaticNaming::FileName,
+            ..stem.clone()
+        };
+        let asm = translate(&sources, &options).unwrap();
+        assert!(asm.contains("@Main.vm.2\n"));
+
+        // files without statics need no prefix
+        let sources = [
+            ("a/Main.vm", "push static 0\n"),
+            ("b/Main.vm", "push constant 0\n"),
+        ];
+        assert!(translate(&sources, &stem).is_ok());
+        let sources = [
+            ("a/Main.vm", "push static 0\n"),
+            ("b/Main.vm", "pop static 0\n"),
+        ];
+        let diagnostics = translate(&sources, &stem).unwrap_err();
+        assert_eq!(diagnostics.error_count(), 1);
+        assert!(translate(&sources, &options).is_err());
+    }
+
+    #[test]
+    fn sorts_files() {
+        let files = ["dir/Main.vm", "dir/Sys.vm", "dir/Array.vm", "dir/Memory.vm"];
+        let sorted = |order: FileOrder| {
+            let mut files = files;
+            order.sort(&mut files).map(|_| files)
+        };
+        assert_eq!(
+            sorted(FileOrder::SysFirst),
+            Ok(["dir/Sys.vm", "dir/Array.vm", "dir/Main.vm", "dir/Memory.vm"])
+        );
+        assert_eq!(
+            sorted(FileOrder::Name),
+            Ok(["dir/Array.vm", "dir/Main.vm", "dir/Memory.vm", "dir/Sys.vm"])
+        );
+        let explicit =
+            |names: &[&str]| FileOrder::Explicit(names.iter().map(|x| x.to_string()).collect());
+        assert_eq!(
+            sorted(explicit(&["Memory.vm", "Main.vm"])),
+            Ok(["dir/Memory.vm", "dir/Main.vm", "dir/Array.vm", "dir/Sys.vm"])
+        );
+        assert_eq!(
+            sorted(explicit(&["Screen.vm"])),
+            Err(String::from("Screen.vm"))
+        );
+    }
+}
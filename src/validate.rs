@@ -0,0 +1,159 @@
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+use crate::{
+    ir::Module,
+    parser::{Command, Segment, Span, Statement},
+};
+
+/// Largest value that can be loaded with an A-instruction.
+const MAX_CONSTANT: usize = 32767;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ValidationError {
+    pub file: String,
+    pub span: Span,
+    pub kind: ValidationErrorKind,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ValidationErrorKind {
+    PopConstant,
+    IndexOutOfRange {
+        segment: Segment,
+        index: usize,
+        max: usize,
+    },
+    ConstantTooLarge(usize),
+}
+
+impl Display for ValidationErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationErrorKind::PopConstant => write!(f, "cannot pop to the constant segment"),
+            ValidationErrorKind::IndexOutOfRange {
+                segment,
+                index,
+                max,
+            } => write!(
+                f,
+                "index {index} is out of range for segment `{segment}` (0-{max})"
+            ),
+            ValidationErrorKind::ConstantTooLarge(value) => write!(
+                f,
+                "constant {value} does not fit in an A-instruction (max {MAX_CONSTANT})"
+            ),
+        }
+    }
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.span.line, self.span.column, self.kind
+        )
+    }
+}
+
+impl Error for ValidationError {}
+
+/// Checks segment/index combinations that parse fine but cannot be translated.
+pub fn validate(module: &Module) -> Vec<ValidationError> {
+    module
+        .statements()
+        .filter_map(|(_, statement)| validate_statement(statement).err())
+        .map(|(span, kind)| ValidationError {
+            file: module.file.clone(),
+            span,
+            kind,
+        })
+        .collect()
+}
+
+fn validate_statement(statement: &Statement) -> Result<(), (Span, ValidationErrorKind)> {
+    let (segment, index, is_pop) = match statement.command {
+        Command::Push(segment, index) => (segment, index, false),
+        Command::Pop(segment, index) => (segment, index, true),
+        _ => return Ok(()),
+    };
+    let segment_span = statement.operand(0);
+    let index_span = statement.operand(1);
+
+    let max = match segment {
+        Segment::Constant if is_pop => {
+            return Err((segment_span, ValidationErrorKind::PopConstant))
+        }
+        Segment::Constant if index > MAX_CONSTANT => {
+            return Err((index_span, ValidationErrorKind::ConstantTooLarge(index)))
+        }
+        Segment::Pointer => 1,
+        Segment::Temp => 7,
+        _ => return Ok(()),
+    };
+
+    if index > max {
+        return Err((
+            index_span,
+            ValidationErrorKind::IndexOutOfRange {
+                segment,
+                index,
+                max,
+            },
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate, ValidationErrorKind};
+    use crate::parser::{Parser, Segment};
+
+    fn errors(source: &str) -> Vec<(usize, usize, ValidationErrorKind)> {
+        let (module, _) = Parser::build("Test.vm", source).unwrap().parse_module();
+        validate(&module)
+            .into_iter()
+            .map(|err| (err.span.line, err.span.column, err.kind))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_valid_indexes() {
+        let source = "push constant 32767\npop pointer 1\npush temp 7\npush static 20\n";
+        assert_eq!(errors(source), vec![]);
+    }
+
+    #[test]
+    fn rejects_invalid_segment_indexes() {
+        let source = "pop constant 5\npush pointer 7\npop temp 12\npush constant 32768\n";
+        assert_eq!(
+            errors(source),
+            vec![
+                (1, 5, ValidationErrorKind::PopConstant),
+                (
+                    2,
+                    14,
+                    ValidationErrorKind::IndexOutOfRange {
+                        segment: Segment::Pointer,
+                        index: 7,
+                        max: 1
+                    }
+                ),
+                (
+                    3,
+                    10,
+                    ValidationErrorKind::IndexOutOfRange {
+                        segment: Segment::Temp,
+                        index: 12,
+                        max: 7
+                    }
+                ),
+                (4, 15, ValidationErrorKind::ConstantTooLarge(32768)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,71 @@
+//! Runs `eq`, `gt` and `lt` on every pair of boundary values on the CPU
+//! emulator, in each code generation mode, including the pairs whose
+//! difference overflows 16 bits.
+
+use vm_translator::{assembler, emulator::Cpu, translate, BootstrapMode, Options};
+
+const VALUES: [i16; 11] = [
+    i16::MIN,
+    i16::MIN + 1,
+    -16384,
+    -2,
+    -1,
+    0,
+    1,
+    2,
+    16384,
+    i16::MAX - 1,
+    i16::MAX,
+];
+
+const RESULTS: i16 = 3000;
+
+// pushes `value`, which constants alone cannot express when negative
+fn push(source: &mut String, value: i16) {
+    match value {
+        i16::MIN => source.push_str("push constant 32767\nneg\npush constant 1\nsub\n"),
+        -32767..=-1 => source.push_str(&format!("push constant {}\nneg\n", -value)),
+        _ => source.push_str(&format!("push constant {value}\n")),
+    }
+}
+
+#[test]
+fn comparisons_match_twos_complement() {
+    let mut source = format!("push constant {RESULTS}\npop pointer 1\n");
+    let mut expected = Vec::new();
+    for (op, compare) in [
+        ("eq", i16::eq as fn(&i16, &i16) -> bool),
+        ("gt", i16::gt),
+        ("lt", i16::lt),
+    ] {
+        for x in VALUES {
+            for y in VALUES {
+                push(&mut source, x);
+                push(&mut source, y);
+                source.push_str(&format!("{op}\npop that {}\n", expected.len()));
+                expected.push((op, x, y, -i16::from(compare(&x, &y))));
+            }
+        }
+    }
+
+    let modes = [(false, false), (true, false), (false, true), (true, true)];
+    for (optimize, shared_runtime) in modes {
+        let options = Options {
+            optimize,
+            shared_runtime,
+            bootstrap: BootstrapMode::None,
+            ..Options::default()
+        };
+        let asm = translate(&[("Main.vm", &source)], &options).unwrap();
+        let mut cpu = Cpu::new(&assembler::assemble_source(&asm).unwrap()).unwrap();
+        cpu.ram_mut()[0] = 256;
+        cpu.run(1_000_000);
+        assert!(cpu.is_halted(), "{options:?}");
+
+        let results = &cpu.ram()[usize::from(RESULTS as u16)..];
+        for (&(op, x, y, expected), &actual) in expected.iter().zip(results) {
+            assert_eq!(actual, expected, "{x} {op} {y} ({options:?})");
+        }
+        assert_eq!(cpu.ram()[0], 256, "{options:?}");
+    }
+}
@@ -0,0 +1,77 @@
+//! Runs every sample under `test/` on the interpreter and, translated, on the
+//! CPU emulator, checking that both agree after every VM command.
+
+use std::{fs, path::Path};
+
+use vm_translator::{
+    differential::Differential, translator::parse_program, BootstrapMode, Options, StaticNaming,
+};
+
+const CHAPTERS: [&str; 4] = [
+    "test/StackArithmetic",
+    "test/MemoryAccess",
+    "test/ProgramFlow",
+    "test/FunctionCalls",
+];
+
+// segment pointers and arguments for the samples without `Sys.init`, which
+// their scripts set up by hand
+const SETUP: [(usize, i16); 6] = [
+    (1, 300),
+    (2, 400),
+    (3, 3000),
+    (4, 3010),
+    (400, 6),
+    (401, 3000),
+];
+
+fn check(dir: &Path, options: &Options) {
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.extension().and_then(|x| x.to_str()) == Some("vm"))
+        .collect();
+    files.sort();
+    let sources: Vec<(String, String)> = files
+        .iter()
+        .map(|x| (x.display().to_string(), fs::read_to_string(x).unwrap()))
+        .collect();
+    let (program, diagnostics) = parse_program(&sources, StaticNaming::default());
+    assert!(!diagnostics.has_errors(), "{diagnostics}");
+
+    let options = Options {
+        bootstrap: BootstrapMode::detect(&program),
+        ..options.clone()
+    };
+    let mut differential = Differential::new(&program, &options)
+        .unwrap_or_else(|err| panic!("{}: {err}", dir.display()));
+    differential.bootstrap().unwrap();
+    if options.bootstrap == BootstrapMode::None {
+        for (address, value) in SETUP {
+            differential.poke(address, value);
+        }
+    }
+    let steps = differential
+        .run(100_000)
+        .unwrap_or_else(|err| panic!("{}: {err}", dir.display()));
+    assert!(steps > 0, "{}", dir.display());
+}
+
+#[test]
+fn samples_agree_with_the_interpreter() {
+    let mut samples = 0;
+    for chapter in CHAPTERS {
+        for dir in fs::read_dir(chapter).unwrap() {
+            let dir = dir.unwrap().path();
+            for shared_runtime in [false, true] {
+                let options = Options {
+                    shared_runtime,
+                    ..Options::default()
+                };
+                check(&dir, &options);
+            }
+            samples += 1;
+        }
+    }
+    assert_eq!(samples, 11);
+}
@@ -0,0 +1,23 @@
+//! Checks that random VM programs behave the same translated as they do on
+//! the interpreter.
+
+use vm_translator::{
+    fuzz::{fuzz, Config},
+    Options,
+};
+
+const CASES: u64 = 300;
+const MAX_STEPS: usize = 20_000;
+
+#[test]
+fn random_programs_agree_with_the_interpreter() {
+    for shared_runtime in [false, true] {
+        let options = Options {
+            shared_runtime,
+            ..Options::default()
+        };
+        if let Some(failure) = fuzz(0, CASES, &Config::default(), &options, MAX_STEPS) {
+            panic!("{failure}");
+        }
+    }
+}
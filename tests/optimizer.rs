@@ -0,0 +1,51 @@
+//! Runs every sample under `test/` in each code generation mode and checks
+//! all of them against the expected `.cmp` output.
+
+use std::fs;
+
+use vm_translator::{assembler, script, translate, BootstrapMode, Options};
+
+#[test]
+fn every_mode_produces_the_same_results() {
+    let mut samples = Vec::new();
+    for chapter in fs::read_dir("test").unwrap() {
+        for sample in fs::read_dir(chapter.unwrap().path()).unwrap() {
+            samples.push(sample.unwrap().path());
+        }
+    }
+    samples.sort();
+    assert!(!samples.is_empty());
+
+    for dir in samples {
+        let name = dir.file_name().unwrap().to_str().unwrap();
+        let modes = [(false, false), (true, false), (false, true), (true, true)];
+        for (optimize, shared_runtime) in modes {
+            let options = Options {
+                optimize,
+                shared_runtime,
+                ..Options::default()
+            };
+            let report =
+                script::run_script_file(&dir.join(format!("{name}.tst")), &options).unwrap();
+            assert_eq!(report.mismatch, None, "{name} ({options:?})");
+        }
+
+        let mut sources: Vec<_> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|x| x.unwrap().path())
+            .filter(|x| x.extension().and_then(|x| x.to_str()) == Some("vm"))
+            .map(|x| (x.display().to_string(), fs::read_to_string(&x).unwrap()))
+            .collect();
+        sources.sort();
+        let size = |optimize| {
+            let options = Options {
+                optimize,
+                bootstrap: BootstrapMode::None,
+                ..Options::default()
+            };
+            let asm = translate(&sources, &options).unwrap();
+            assembler::assemble_source(&asm).unwrap().len()
+        };
+        assert!(size(true) < size(false), "{name} was not optimized");
+    }
+}
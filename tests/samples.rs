@@ -0,0 +1,50 @@
+//! Translates every sample under `test/`, runs it according to its `.tst`
+//! and `VME.tst` scripts and checks the output against the `.cmp` file.
+
+use std::{fs, path::PathBuf};
+
+use vm_translator::{script, Options};
+
+const CHAPTERS: [&str; 4] = [
+    "test/StackArithmetic",
+    "test/MemoryAccess",
+    "test/ProgramFlow",
+    "test/FunctionCalls",
+];
+
+fn samples() -> Vec<PathBuf> {
+    let mut samples: Vec<PathBuf> = CHAPTERS
+        .iter()
+        .flat_map(|chapter| fs::read_dir(chapter).unwrap())
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.is_dir())
+        .collect();
+    samples.sort();
+    samples
+}
+
+fn run(script: PathBuf) {
+    let report = script::run_script_file(&script, &Options::default())
+        .unwrap_or_else(|err| panic!("{}: {err}", script.display()));
+    if let Some(mismatch) = report.mismatch {
+        panic!("{}: {mismatch}\n{}", script.display(), report.output);
+    }
+}
+
+#[test]
+fn translated_samples_match_cmp() {
+    let samples = samples();
+    assert_eq!(samples.len(), 11);
+    for dir in samples {
+        let name = dir.file_name().unwrap().to_str().unwrap().to_owned();
+        run(dir.join(format!("{name}.tst")));
+    }
+}
+
+#[test]
+fn interpreted_samples_match_cmp() {
+    for dir in samples() {
+        let name = dir.file_name().unwrap().to_str().unwrap().to_owned();
+        run(dir.join(format!("{name}VME.tst")));
+    }
+}